@@ -0,0 +1,545 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use cooklang_rs::format::format;
+use cooklang_rs::lint::{self, LintConfig};
+use cooklang_rs::parser::{self, Part, Recipe, Step};
+use cooklang_rs::shopping;
+use indexmap::IndexMap;
+
+/// Parse and inspect cooklang recipes.
+///
+/// Every command reads the given files, or stdin when there are none or the
+/// file is `-`, given at most once.
+#[derive(Parser)]
+#[command(name = "cook", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the parsed recipes
+    Parse {
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+        files: Vec<PathBuf>,
+    },
+    /// Print the ingredients of all the recipes, summed like a shopping list
+    Ingredients { files: Vec<PathBuf> },
+    /// Exit with an error status if a recipe does not parse
+    Check { files: Vec<PathBuf> },
+    /// Rewrite the files in the canonical layout, stdin is printed
+    Fmt {
+        /// list the files that are not formatted instead, and fail if there are some
+        #[arg(long)]
+        check: bool,
+        files: Vec<PathBuf>,
+    },
+    /// Report quality problems, and fail if one of them is an error
+    Lint {
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+        /// TOML file setting the severity of the rules
+        #[arg(long)]
+        config: Option<PathBuf>,
+        files: Vec<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Json,
+    Yaml,
+    Text,
+}
+
+/// A recipe source, with the name used in messages
+struct Input {
+    name: String,
+    text: String,
+}
+
+fn read_inputs(files: &[PathBuf]) -> io::Result<Vec<Input>> {
+    let stdin = || {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text)?;
+        Ok(Input {
+            name: "<stdin>".to_string(),
+            text,
+        })
+    };
+    if files.is_empty() {
+        return Ok(vec![stdin()?]);
+    }
+    // stdin can only be read once
+    if files.iter().filter(|path| path.as_os_str() == "-").count() > 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "`-` can be given only once",
+        ));
+    }
+    files
+        .iter()
+        .map(|path| {
+            if path.as_os_str() == "-" {
+                return stdin();
+            }
+            let name = path.display().to_string();
+            match fs::read_to_string(path) {
+                Ok(text) => Ok(Input { name, text }),
+                Err(e) => Err(io::Error::new(e.kind(), format!("{name}: {e}"))),
+            }
+        })
+        .collect()
+}
+
+/// Recipes that parse, and the errors of the others as `name:line:column: message`
+fn parse_inputs(inputs: Vec<Input>) -> (Vec<(String, Recipe)>, Vec<String>) {
+    let mut recipes = Vec::new();
+    let mut errors = Vec::new();
+    for Input { name, text } in inputs {
+        match parser::parse(text) {
+            Ok(recipe) => recipes.push((name, recipe)),
+            Err(e) => errors.push(format!("{name}:{e}")),
+        }
+    }
+    (recipes, errors)
+}
+
+/// step as plain text, with the names of its items
+fn step_text(step: &Step) -> String {
+    let mut out = String::new();
+    for (i, part) in step.parts.iter().enumerate() {
+        let glued = matches!(part, Part::Text(t) if t.value.starts_with(['.', ',', ';']));
+        if i > 0 && !glued {
+            out.push(' ');
+        }
+        match part {
+            Part::Ingredient(i) if i.note.is_empty() => out.push_str(&i.name),
+            Part::Ingredient(i) => out.push_str(&format!("{} ({})", i.name, i.note)),
+            Part::Cookware(c) => out.push_str(&c.name),
+            Part::Timer(t) => out.push_str(format!("{} {}", t.quantity, t.units).trim()),
+            Part::Text(t) => out.push_str(&t.value),
+            Part::Note(n) => out.push_str(&format!("> {}", n.value)),
+        }
+    }
+    out
+}
+
+/// `name: quantity units`, or only the name when there is no amount
+fn amount_line(name: &str, quantity: &str, units: &str) -> String {
+    let amount = format!("{quantity} {units}");
+    match amount.trim() {
+        "" => name.to_string(),
+        amount => format!("{name}: {amount}"),
+    }
+}
+
+fn to_text(recipe: &Recipe) -> String {
+    let mut sections = Vec::new();
+    if !recipe.metadata.is_empty() {
+        let lines: Vec<_> = recipe
+            .metadata
+            .iter()
+            .map(|(key, value)| format!("{key}: {value}\n"))
+            .collect();
+        sections.push(lines.concat());
+    }
+    let mut section = |title: &str, lines: Vec<String>| {
+        if !lines.is_empty() {
+            let lines: Vec<_> = lines.iter().map(|l| format!("  {l}\n")).collect();
+            sections.push(format!("{title}:\n{}", lines.concat()));
+        }
+    };
+    section(
+        "Ingredients",
+        recipe
+            .ingredients()
+            .iter()
+            .map(|i| amount_line(&i.name, &i.quantity.to_string(), &i.units))
+            .collect(),
+    );
+    section(
+        "Cookware",
+        recipe
+            .cookware()
+            .iter()
+            .map(|c| amount_line(&c.name, &c.quantity.to_string(), ""))
+            .collect(),
+    );
+    let mut steps = Vec::new();
+    let mut number = 0;
+    for s in &recipe.sections {
+        if !s.name.is_empty() {
+            steps.push(format!("== {} ==", s.name));
+        }
+        for step in s.steps(recipe) {
+            if step.is_note() {
+                steps.push(step_text(step));
+            } else {
+                number += 1;
+                steps.push(format!("{number}. {}", step_text(step)));
+            }
+        }
+    }
+    section("Steps", steps);
+    sections.join("\n")
+}
+
+/// A single recipe is printed as is, several are keyed by their name
+fn render(recipes: &[(String, Recipe)], format: Format) -> Result<String, String> {
+    let by_name: IndexMap<_, _> = recipes.iter().map(|(n, r)| (n, r)).collect();
+    match (format, recipes) {
+        (Format::Text, [(_, recipe)]) => Ok(to_text(recipe)),
+        (Format::Text, _) => Ok(recipes
+            .iter()
+            .map(|(name, recipe)| format!("==> {name} <==\n{}", to_text(recipe)))
+            .collect::<Vec<_>>()
+            .join("\n")),
+        (Format::Json, [(_, recipe)]) => serde_json::to_string_pretty(recipe)
+            .map(|json| json + "\n")
+            .map_err(|e| e.to_string()),
+        (Format::Json, _) => serde_json::to_string_pretty(&by_name)
+            .map(|json| json + "\n")
+            .map_err(|e| e.to_string()),
+        (Format::Yaml, [(_, recipe)]) => serde_yaml::to_string(recipe).map_err(|e| e.to_string()),
+        (Format::Yaml, _) => serde_yaml::to_string(&by_name).map_err(|e| e.to_string()),
+    }
+}
+
+fn ingredients_text(recipes: &[(String, Recipe)]) -> String {
+    shopping::shopping_list(recipes.iter().map(|(_, r)| r))
+        .iter()
+        .map(|item| amount_line(&item.name, &item.quantity.to_string(), &item.units) + "\n")
+        .collect()
+}
+
+/// Format the inputs, read from `files`, in place or to stdout
+fn fmt(files: &[PathBuf], inputs: Vec<Input>, check: bool) -> ExitCode {
+    // stdin when there are no files
+    let paths = files
+        .iter()
+        .map(|path| Some(path).filter(|p| p.as_os_str() != "-"))
+        .chain(files.is_empty().then_some(None));
+    let mut status = ExitCode::SUCCESS;
+    for (path, Input { name, text }) in paths.zip(inputs) {
+        let formatted = match format(&text) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("{name}:{e}");
+                status = ExitCode::FAILURE;
+                continue;
+            }
+        };
+        match path {
+            _ if check => {
+                if formatted != text {
+                    println!("{name}");
+                    status = ExitCode::FAILURE;
+                }
+            }
+            Some(path) => {
+                if formatted != text {
+                    if let Err(e) = fs::write(path, formatted) {
+                        eprintln!("cook: {name}: {e}");
+                        return ExitCode::from(2);
+                    }
+                }
+            }
+            None => print!("{formatted}"),
+        }
+    }
+    status
+}
+
+/// A lint of a recipe, or its parse error reported as the `parse` rule
+fn lint_reports(inputs: Vec<Input>, config: &LintConfig) -> Vec<serde_json::Value> {
+    let report = |file: &str, rule: &str, severity: String, line, column, message: &str| {
+        serde_json::json!({
+            "file": file,
+            "rule": rule,
+            "severity": severity,
+            "line": line,
+            "column": column,
+            "message": message,
+        })
+    };
+    let mut reports = Vec::new();
+    for Input { name, text } in inputs {
+        match lint::lint(&text, config) {
+            Ok(lints) => reports.extend(lints.iter().map(|l| {
+                let severity = l.severity.to_string();
+                report(
+                    &name,
+                    l.rule,
+                    severity,
+                    l.span.line,
+                    l.span.column,
+                    &l.message,
+                )
+            })),
+            Err(e) => {
+                let severity = lint::Severity::Error.to_string();
+                reports.push(report(
+                    &name, "parse", severity, e.line, e.column, &e.message,
+                ));
+            }
+        }
+    }
+    reports
+}
+
+fn render_lints(reports: &[serde_json::Value], format: Format) -> Result<String, String> {
+    match format {
+        Format::Text => Ok(reports
+            .iter()
+            .map(|r| {
+                let field = |key: &str| r[key].as_str().unwrap_or_default().to_string();
+                format!(
+                    "{}:{}:{}: {}: {} [{}]\n",
+                    field("file"),
+                    r["line"],
+                    r["column"],
+                    field("severity"),
+                    field("message"),
+                    field("rule")
+                )
+            })
+            .collect()),
+        Format::Json => serde_json::to_string_pretty(reports)
+            .map(|json| json + "\n")
+            .map_err(|e| e.to_string()),
+        Format::Yaml => serde_yaml::to_string(reports).map_err(|e| e.to_string()),
+    }
+}
+
+/// Print the lints of the inputs, failing when one of them is an error
+fn lint_command(inputs: Vec<Input>, config: Option<&PathBuf>, format: Format) -> ExitCode {
+    let config = match config {
+        None => LintConfig::default(),
+        Some(path) => match fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|toml| LintConfig::from_toml(&toml).map_err(|e| e.to_string()))
+        {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("cook: {}: {e}", path.display());
+                return ExitCode::from(2);
+            }
+        },
+    };
+    let reports = lint_reports(inputs, &config);
+    match render_lints(&reports, format) {
+        Ok(out) => print!("{out}"),
+        Err(e) => {
+            eprintln!("cook: {e}");
+            return ExitCode::from(2);
+        }
+    }
+    if reports.iter().any(|r| r["severity"] == "error") {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let files = match &cli.command {
+        Command::Parse { files, .. }
+        | Command::Ingredients { files }
+        | Command::Check { files }
+        | Command::Fmt { files, .. }
+        | Command::Lint { files, .. } => files,
+    };
+    let inputs = match read_inputs(files) {
+        Ok(inputs) => inputs,
+        Err(e) => {
+            eprintln!("cook: {e}");
+            return ExitCode::from(2);
+        }
+    };
+    match &cli.command {
+        Command::Fmt { check, .. } => return fmt(files, inputs, *check),
+        Command::Lint { format, config, .. } => {
+            return lint_command(inputs, config.as_ref(), *format)
+        }
+        _ => {}
+    }
+    let (recipes, errors) = parse_inputs(inputs);
+    for error in &errors {
+        eprintln!("{error}");
+    }
+    for (name, recipe) in &recipes {
+        for warning in &recipe.warnings {
+            let (line, column) = (warning.span.line, warning.span.column);
+            eprintln!("{name}:{line}:{column}: warning: {}", warning.message);
+        }
+    }
+    match cli.command {
+        Command::Parse { format, .. } => match render(&recipes, format) {
+            Ok(out) => print!("{out}"),
+            Err(e) => {
+                eprintln!("cook: {e}");
+                return ExitCode::from(2);
+            }
+        },
+        Command::Ingredients { .. } => print!("{}", ingredients_text(&recipes)),
+        Command::Check { .. } | Command::Fmt { .. } | Command::Lint { .. } => {}
+    }
+    if errors.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn recipes(sources: &[(&str, &str)]) -> Vec<(String, Recipe)> {
+        let inputs = sources
+            .iter()
+            .map(|(name, text)| Input {
+                name: name.to_string(),
+                text: text.to_string(),
+            })
+            .collect();
+        let (recipes, errors) = parse_inputs(inputs);
+        assert_eq!(errors, Vec::<String>::new());
+        recipes
+    }
+
+    #[test]
+    fn test_parse_inputs() {
+        let inputs = vec![
+            Input {
+                name: "good.cook".to_string(),
+                text: "Add @salt".to_string(),
+            },
+            Input {
+                name: "bad.cook".to_string(),
+                text: "Add @salt\nthen @{2}".to_string(),
+            },
+        ];
+        let (recipes, errors) = parse_inputs(inputs);
+        assert_eq!(recipes.len(), 1);
+        assert_eq!(errors, vec!["bad.cook:2:6: expected a name after `@`"]);
+    }
+
+    #[test]
+    fn test_text() {
+        let recipes = recipes(&[(
+            "pancakes.cook",
+            ">> servings: 2\n\
+            Mix @flour{250%g}, @eggs{2}(beaten) and @salt in a #bowl.\n\
+            == Later ==\n\
+            > Cover it\n\
+            Rest for ~{10%minutes}",
+        )]);
+        assert_eq!(
+            render(&recipes, Format::Text).unwrap(),
+            "servings: 2\n\
+            \n\
+            Ingredients:\n  flour: 250 g\n  eggs: 2\n  salt\n\
+            \n\
+            Cookware:\n  bowl\n\
+            \n\
+            Steps:\n  1. Mix flour, eggs (beaten) and salt in a bowl.\n  == Later ==\n  > Cover it\n  2. Rest for 10 minutes\n"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let single = recipes(&[("a.cook", "Add @salt")]);
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&single, Format::Json).unwrap()).unwrap();
+        assert_eq!(json["steps"][0][1]["name"], "salt");
+
+        let several = recipes(&[("a.cook", "Add @salt"), ("b.cook", "Add @pepper")]);
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&several, Format::Json).unwrap()).unwrap();
+        assert_eq!(json["b.cook"]["steps"][0][1]["name"], "pepper");
+
+        let yaml = render(&several, Format::Yaml).unwrap();
+        assert!(yaml.starts_with("a.cook:\n"));
+    }
+
+    #[test]
+    fn test_ingredients() {
+        let recipes = recipes(&[
+            ("a.cook", "Mix @flour{250%g} and @salt"),
+            ("b.cook", "Knead @flour{1%kg} with @water{300%ml} and @salt"),
+        ]);
+        assert_eq!(
+            ingredients_text(&recipes),
+            "flour: 1250 g\nsalt\nwater: 300 ml\n"
+        );
+    }
+
+    #[test]
+    fn test_read_inputs() {
+        let files = ["a.cook", "-", "-"].map(PathBuf::from);
+        let Err(error) = read_inputs(&files) else {
+            panic!("stdin read twice");
+        };
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(error.to_string(), "`-` can be given only once");
+    }
+
+    #[test]
+    fn test_fmt() {
+        let dir = std::env::temp_dir().join(format!("cooklang-cli-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("chilli.cook");
+        fs::write(&path, "Add @chilli{ 3 % items }  ").unwrap();
+        let files = [path.clone()];
+
+        let status = fmt(&files, read_inputs(&files).unwrap(), true);
+        assert_eq!(status, ExitCode::FAILURE);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "Add @chilli{ 3 % items }  "
+        );
+
+        let status = fmt(&files, read_inputs(&files).unwrap(), false);
+        assert_eq!(status, ExitCode::SUCCESS);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "Add @chilli{3%items}\n");
+
+        let status = fmt(&files, read_inputs(&files).unwrap(), true);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(status, ExitCode::SUCCESS);
+    }
+
+    #[test]
+    fn test_lint() {
+        let inputs = vec![
+            Input {
+                name: "a.cook".to_string(),
+                text: ">> serving: 2\nBoil for ~{10}.".to_string(),
+            },
+            Input {
+                name: "b.cook".to_string(),
+                text: "Add @{1}".to_string(),
+            },
+        ];
+        let reports = lint_reports(inputs, &LintConfig::default());
+        assert_eq!(
+            render_lints(&reports, Format::Text).unwrap(),
+            "a.cook:1:1: warning: unknown metadata key `serving`, did you mean `servings`? [misspelled-metadata]\n\
+            a.cook:2:10: warning: timer of 10 has no units [timer-without-units]\n\
+            b.cook:1:5: error: expected a name after `@` [parse]\n"
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&render_lints(&reports, Format::Json).unwrap()).unwrap();
+        assert_eq!(json[1]["rule"], "timer-without-units");
+        assert_eq!(json[1]["line"], 2);
+    }
+}
@@ -0,0 +1,19 @@
+use std::process::ExitCode;
+
+use lsp_server::Connection;
+
+mod server;
+
+/// Language server for cooklang recipes, speaking LSP over stdin and stdout
+fn main() -> ExitCode {
+    let (connection, io_threads) = Connection::stdio();
+    let result = server::run(&connection);
+    drop(connection);
+    match result.and_then(|_| Ok(io_threads.join()?)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("cook-lsp: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,764 @@
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use cooklang_rs::parser::{self, Ingredient, Part, Recipe};
+use cooklang_rs::resolve::link_path;
+use cooklang_rs::shopping::ShoppingList;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as LspNotification, PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, Request as LspRequest,
+};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    Diagnostic, DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse,
+    GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, InitializeParams, Location, MarkupContent, MarkupKind, OneOf,
+    Position, PublishDiagnosticsParams, Range, ServerCapabilities, SymbolKind,
+    TextDocumentPositionParams, TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Documents are sent whole on every change
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec!["@".to_string(), "#".to_string()]),
+            ..Default::default()
+        }),
+        definition_provider: Some(OneOf::Left(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    }
+}
+
+/// Serve the client of `connection` until it asks to exit
+pub fn run(connection: &Connection) -> Result<()> {
+    let params = connection.initialize(serde_json::to_value(capabilities())?)?;
+    let params: InitializeParams = serde_json::from_value(params)?;
+    let mut server = Server::default();
+    for root in roots(&params) {
+        server.index_dir(&root);
+    }
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+                connection
+                    .sender
+                    .send(Message::Response(server.request(request)))?;
+            }
+            Message::Notification(notification) => {
+                if let Some(notification) = server.notification(notification) {
+                    connection
+                        .sender
+                        .send(Message::Notification(notification))?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// directories of the workspace folders, or the root directory
+#[allow(deprecated)]
+fn roots(params: &InitializeParams) -> Vec<PathBuf> {
+    let uris: Vec<&Url> = match &params.workspace_folders {
+        Some(folders) => folders.iter().map(|f| &f.uri).collect(),
+        None => params.root_uri.iter().collect(),
+    };
+    uris.iter().filter_map(|u| u.to_file_path().ok()).collect()
+}
+
+/// LSP position of the byte `offset` of `text`, in UTF-16 code units
+fn position(text: &str, offset: usize) -> Position {
+    let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    Position::new(
+        text[..offset].matches('\n').count() as u32,
+        text[line_start..offset].encode_utf16().count() as u32,
+    )
+}
+
+/// byte offset of the LSP `position` in `text`
+fn offset(text: &str, position: Position) -> usize {
+    let line_start: usize = text
+        .split_inclusive('\n')
+        .take(position.line as usize)
+        .map(str::len)
+        .sum();
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= position.character || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16() as u32;
+    }
+    text.len()
+}
+
+fn range(text: &str, start: usize, end: usize) -> Range {
+    Range::new(position(text, start), position(text, end))
+}
+
+/// parse errors and warnings of `text`
+fn diagnostics(text: &str) -> Vec<Diagnostic> {
+    let diagnostic = |range, severity, message: &str| Diagnostic {
+        range,
+        severity: Some(severity),
+        source: Some("cook".to_string()),
+        message: message.to_string(),
+        ..Default::default()
+    };
+    let (_, diagnostics) = parser::parse_recovering(text);
+    diagnostics
+        .iter()
+        .map(|d| match d {
+            parser::Diagnostic::Warning(w) => {
+                let range = range(text, w.span.start, w.span.end);
+                diagnostic(range, DiagnosticSeverity::WARNING, &w.message)
+            }
+            parser::Diagnostic::Error(e) => {
+                // the character the error points at
+                let end = text[e.offset..]
+                    .chars()
+                    .next()
+                    .map_or(e.offset, |c| e.offset + c.len_utf8());
+                let range = range(text, e.offset, end);
+                diagnostic(range, DiagnosticSeverity::ERROR, &e.message)
+            }
+        })
+        .collect()
+}
+
+/// the ingredient written at byte `offset`
+fn ingredient_at(recipe: &Recipe, offset: usize) -> Option<&Ingredient> {
+    recipe
+        .steps
+        .iter()
+        .flat_map(|s| &s.parts)
+        .find_map(|part| match part {
+            Part::Ingredient(i) if (i.span.start..=i.span.end).contains(&offset) => Some(i),
+            _ => None,
+        })
+}
+
+/// Ingredient and cookware names of a recipe, for completion
+#[derive(Default)]
+struct Names {
+    ingredients: BTreeSet<String>,
+    cookware: BTreeSet<String>,
+}
+
+impl Names {
+    fn new(recipe: &Recipe) -> Self {
+        let mut names = Names::default();
+        for part in recipe.steps.iter().flat_map(|s| &s.parts) {
+            match part {
+                Part::Ingredient(i) if !i.recipe_link => {
+                    names.ingredients.insert(i.name.clone());
+                }
+                Part::Cookware(c) => {
+                    names.cookware.insert(c.name.clone());
+                }
+                _ => {}
+            }
+        }
+        names
+    }
+}
+
+#[derive(Default)]
+struct Server {
+    /// text of the open documents
+    documents: HashMap<Url, String>,
+    /// names used by the recipes of the workspace and the open documents
+    names: HashMap<Url, Names>,
+}
+
+/// answer `request` with `handler`, or with an error when its parameters are invalid
+fn handle<R: LspRequest>(
+    request: Request,
+    handler: impl FnOnce(R::Params) -> R::Result,
+) -> Response {
+    let id = request.id.clone();
+    match request.extract(R::METHOD) {
+        Ok((id, params)) => Response::new_ok(id, handler(params)),
+        Err(e) => Response::new_err(id, ErrorCode::InvalidParams as i32, e.to_string()),
+    }
+}
+
+impl Server {
+    /// read the names of the `.cook` files under `dir`, hidden directories excepted
+    fn index_dir(&mut self, dir: &Path) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() && !entry.file_name().to_string_lossy().starts_with('.') {
+                self.index_dir(&path);
+            } else if path.extension().is_some_and(|e| e == "cook") {
+                if let (Ok(uri), Ok(text)) = (Url::from_file_path(&path), fs::read_to_string(&path))
+                {
+                    self.index(uri, &text);
+                }
+            }
+        }
+    }
+
+    fn index(&mut self, uri: Url, text: &str) {
+        let (recipe, _) = parser::parse_recovering(text);
+        self.names.insert(uri, Names::new(&recipe));
+    }
+
+    /// the open document `uri` and its recipe, without the lines with errors
+    fn recipe(&self, uri: &Url) -> Option<(&str, Recipe)> {
+        let text = self.documents.get(uri)?;
+        Some((text, parser::parse_recovering(text).0))
+    }
+
+    /// keep track of the open documents, and publish their diagnostics
+    fn notification(&mut self, notification: Notification) -> Option<Notification> {
+        let (uri, text) = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams =
+                    notification.extract(DidOpenTextDocument::METHOD).ok()?;
+                (params.text_document.uri, Some(params.text_document.text))
+            }
+            DidChangeTextDocument::METHOD => {
+                let mut params: DidChangeTextDocumentParams =
+                    notification.extract(DidChangeTextDocument::METHOD).ok()?;
+                (
+                    params.text_document.uri,
+                    Some(params.content_changes.pop()?.text),
+                )
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams =
+                    notification.extract(DidCloseTextDocument::METHOD).ok()?;
+                (params.text_document.uri, None)
+            }
+            _ => return None,
+        };
+        let diagnostics = match text {
+            Some(text) => {
+                self.index(uri.clone(), &text);
+                let diagnostics = diagnostics(&text);
+                self.documents.insert(uri.clone(), text);
+                diagnostics
+            }
+            None => {
+                self.documents.remove(&uri);
+                self.names.remove(&uri);
+                // back to the names of the file as saved, if it is one
+                let saved = uri.to_file_path().ok().map(fs::read_to_string);
+                if let Some(Ok(text)) = saved {
+                    self.index(uri.clone(), &text);
+                }
+                Vec::new()
+            }
+        };
+        Some(Notification::new(
+            PublishDiagnostics::METHOD.to_string(),
+            PublishDiagnosticsParams::new(uri, diagnostics, None),
+        ))
+    }
+
+    fn request(&self, request: Request) -> Response {
+        match request.method.as_str() {
+            HoverRequest::METHOD => handle::<HoverRequest>(request, |p| self.hover(p)),
+            Completion::METHOD => handle::<Completion>(request, |p| self.completion(p)),
+            GotoDefinition::METHOD => handle::<GotoDefinition>(request, |p| self.definition(p)),
+            DocumentSymbolRequest::METHOD => {
+                handle::<DocumentSymbolRequest>(request, |p| self.symbols(p))
+            }
+            _ => Response::new_err(
+                request.id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unsupported request `{}`", request.method),
+            ),
+        }
+    }
+
+    /// total of the ingredient in the recipe, summed like a shopping list
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let TextDocumentPositionParams {
+            text_document,
+            position,
+        } = params.text_document_position_params;
+        let (text, recipe) = self.recipe(&text_document.uri)?;
+        let ingredient = ingredient_at(&recipe, offset(text, position))?;
+        let mut list = ShoppingList::new();
+        for part in recipe.steps.iter().flat_map(|s| &s.parts) {
+            match part {
+                Part::Ingredient(i) if i.name == ingredient.name => list.add_ingredient(i),
+                _ => {}
+            }
+        }
+        let amounts: Vec<_> = list
+            .items()
+            .iter()
+            .map(|item| {
+                format!("{} {}", item.quantity, item.units)
+                    .trim()
+                    .to_string()
+            })
+            .filter(|amount| !amount.is_empty())
+            .collect();
+        let value = match amounts.as_slice() {
+            [] => format!("**{}**", ingredient.name),
+            amounts => format!("**{}**: {}", ingredient.name, amounts.join(" + ")),
+        };
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: Some(range(text, ingredient.span.start, ingredient.span.end)),
+        })
+    }
+
+    /// names of the workspace after a `@` or a `#`
+    fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let TextDocumentPositionParams {
+            text_document,
+            position,
+        } = params.text_document_position;
+        let text = self.documents.get(&text_document.uri)?;
+        let offset = offset(text, position);
+        let line = &text[text[..offset].rfind('\n').map_or(0, |i| i + 1)..offset];
+        let marker = line.rfind(['@', '#'])?;
+        if line[marker..].contains(['{', '}']) {
+            return None;
+        }
+        let (names, kind): (BTreeSet<&String>, _) = match &line[marker..=marker] {
+            "@" => (
+                self.names.values().flat_map(|n| &n.ingredients).collect(),
+                CompletionItemKind::VALUE,
+            ),
+            _ => (
+                self.names.values().flat_map(|n| &n.cookware).collect(),
+                CompletionItemKind::PROPERTY,
+            ),
+        };
+        let items = names
+            .into_iter()
+            .map(|name| CompletionItem {
+                label: name.clone(),
+                kind: Some(kind),
+                // several words need braces
+                insert_text: name.contains(' ').then(|| format!("{name}{{}}")),
+                ..Default::default()
+            })
+            .collect();
+        Some(CompletionResponse::Array(items))
+    }
+
+    /// the file of a recipe link, or the definition of an `@&` reference
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let TextDocumentPositionParams {
+            text_document,
+            position,
+        } = params.text_document_position_params;
+        let (text, recipe) = self.recipe(&text_document.uri)?;
+        let ingredient = ingredient_at(&recipe, offset(text, position))?;
+        let location = if ingredient.recipe_link {
+            let path = link_path(&text_document.uri.to_file_path().ok()?, &ingredient.name);
+            Location::new(Url::from_file_path(path).ok()?, Range::default())
+        } else if ingredient.reference {
+            let definition =
+                recipe
+                    .steps
+                    .iter()
+                    .flat_map(|s| &s.parts)
+                    .find_map(|part| match part {
+                        Part::Ingredient(i) if i.name == ingredient.name && !i.reference => Some(i),
+                        _ => None,
+                    })?;
+            Location::new(
+                text_document.uri,
+                range(text, definition.span.start, definition.span.end),
+            )
+        } else {
+            return None;
+        };
+        Some(GotoDefinitionResponse::Scalar(location))
+    }
+
+    /// the named sections, from their header to their last step
+    fn symbols(&self, params: DocumentSymbolParams) -> Option<DocumentSymbolResponse> {
+        let (text, recipe) = self.recipe(&params.text_document.uri)?;
+        let symbols = recipe
+            .sections
+            .iter()
+            .filter(|s| !s.name.is_empty())
+            .map(|s| {
+                let end = s.steps(&recipe).last().map_or(s.span.end, |l| l.span.end);
+                #[allow(deprecated)]
+                DocumentSymbol {
+                    name: s.name.clone(),
+                    detail: None,
+                    kind: SymbolKind::NAMESPACE,
+                    tags: None,
+                    deprecated: None,
+                    range: range(text, s.span.start, end),
+                    selection_range: range(text, s.span.start, s.span.end),
+                    children: None,
+                }
+            })
+            .collect();
+        Some(DocumentSymbolResponse::Nested(symbols))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::notification::{Exit, Initialized};
+    use lsp_types::request::{Initialize, Shutdown};
+    use lsp_types::{
+        InitializedParams, TextDocumentContentChangeEvent, TextDocumentIdentifier,
+        TextDocumentItem, VersionedTextDocumentIdentifier,
+    };
+    use pretty_assertions::assert_eq;
+    use std::thread;
+
+    /// A client talking to a server running in a thread
+    struct Client {
+        connection: Connection,
+        server: thread::JoinHandle<Result<()>>,
+        next_id: i32,
+    }
+
+    impl Client {
+        fn start(root: Option<&Path>) -> Self {
+            let (server, connection) = Connection::memory();
+            let mut client = Client {
+                connection,
+                server: thread::spawn(move || run(&server)),
+                next_id: 0,
+            };
+            #[allow(deprecated)]
+            let params = InitializeParams {
+                root_uri: root.map(|r| Url::from_directory_path(r).unwrap()),
+                ..Default::default()
+            };
+            client.request::<Initialize>(params);
+            client.notify::<Initialized>(InitializedParams {});
+            client
+        }
+
+        fn request<R: LspRequest>(&mut self, params: R::Params) -> R::Result {
+            self.next_id += 1;
+            let request = Request::new(self.next_id.into(), R::METHOD.to_string(), params);
+            self.connection.sender.send(request.into()).unwrap();
+            match self.connection.receiver.recv().unwrap() {
+                Message::Response(response) => {
+                    serde_json::from_value(response.result.unwrap()).unwrap()
+                }
+                message => panic!("expected a response, got {message:?}"),
+            }
+        }
+
+        fn notify<N: LspNotification>(&self, params: N::Params) {
+            let notification = Notification::new(N::METHOD.to_string(), params);
+            self.connection.sender.send(notification.into()).unwrap();
+        }
+
+        /// open `text` and return its diagnostics
+        fn open(&self, uri: &Url, text: &str) -> Vec<Diagnostic> {
+            self.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem::new(
+                    uri.clone(),
+                    "cooklang".to_string(),
+                    1,
+                    text.to_string(),
+                ),
+            });
+            self.diagnostics()
+        }
+
+        fn diagnostics(&self) -> Vec<Diagnostic> {
+            match self.connection.receiver.recv().unwrap() {
+                Message::Notification(n) => {
+                    let params: PublishDiagnosticsParams =
+                        n.extract(PublishDiagnostics::METHOD).unwrap();
+                    params.diagnostics
+                }
+                message => panic!("expected diagnostics, got {message:?}"),
+            }
+        }
+
+        fn stop(mut self) {
+            self.request::<Shutdown>(());
+            self.notify::<Exit>(());
+            self.server.join().unwrap().unwrap();
+        }
+    }
+
+    fn uri(path: &str) -> Url {
+        Url::parse(&format!("file:///recipes/{path}")).unwrap()
+    }
+
+    /// position of the first `needle` in `text`
+    fn at(text: &str, needle: &str) -> TextDocumentPositionParams {
+        TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier::new(uri("test.cook")),
+            position: position(text, text.find(needle).unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_positions() {
+        let text = "Mélanger\n🥚 @eggs";
+        assert_eq!(position(text, 15), Position::new(1, 3));
+        assert_eq!(offset(text, Position::new(1, 3)), 15);
+        assert_eq!(offset(text, Position::new(0, 99)), 9);
+        assert_eq!(offset(text, Position::new(5, 0)), text.len());
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let client = Client::start(None);
+        let uri = uri("test.cook");
+        let diagnostics = client.open(&uri, "Add @salt\nthen @{2}\nin a #bowl\nor {3}");
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "expected a name after `@`",
+                "amount is not attached to an ingredient, cookware or timer"
+            ]
+        );
+        assert_eq!(
+            diagnostics[0].range,
+            Range::new(Position::new(1, 5), Position::new(1, 6))
+        );
+
+        client.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier::new(uri.clone(), 2),
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "---\nservings: 2\n---\n>> servings: 4\nAdd @salt".to_string(),
+            }],
+        });
+        let diagnostics = client.diagnostics();
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::WARNING));
+        assert_eq!(
+            diagnostics[0].range,
+            Range::new(Position::new(3, 0), Position::new(3, 14))
+        );
+
+        client.notify::<DidCloseTextDocument>(DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier::new(uri),
+        });
+        assert_eq!(client.diagnostics(), Vec::new());
+        client.stop();
+    }
+
+    #[test]
+    fn test_hover() {
+        let mut client = Client::start(None);
+        let text = "Mix @flour{200%g} and @salt.\nAdd @flour{1%kg}, @&flour{50%g} and @&salt{}.";
+        client.open(&uri("test.cook"), text);
+        let hover = |client: &mut Client, needle| {
+            let params = HoverParams {
+                text_document_position_params: at(text, needle),
+                work_done_progress_params: Default::default(),
+            };
+            client
+                .request::<HoverRequest>(params)
+                .map(|h| match h.contents {
+                    HoverContents::Markup(m) => m.value,
+                    contents => panic!("unexpected hover {contents:?}"),
+                })
+        };
+        assert_eq!(
+            hover(&mut client, "flour").as_deref(),
+            Some("**flour**: 1250 g")
+        );
+        assert_eq!(hover(&mut client, "&salt").as_deref(), Some("**salt**"));
+        assert_eq!(hover(&mut client, "Mix"), None);
+        client.stop();
+    }
+
+    #[test]
+    fn test_completion() {
+        let dir = std::env::temp_dir().join(format!("cooklang-lsp-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sweet")).unwrap();
+        fs::write(
+            dir.join("sweet/Pancakes.cook"),
+            "Mix @flour{} and @eggs in a #mixing bowl{}",
+        )
+        .unwrap();
+        let mut client = Client::start(Some(&dir));
+
+        // the lines with errors are left out
+        client.open(&uri("test.cook"), "Whisk @milk with a #whisk\nAdd ");
+        client.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier::new(uri("test.cook"), 2),
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "Whisk @milk with a #whisk\nAdd @".to_string(),
+            }],
+        });
+        client.diagnostics();
+        let complete = |client: &mut Client, position| {
+            let params = CompletionParams {
+                text_document_position: TextDocumentPositionParams {
+                    text_document: TextDocumentIdentifier::new(uri("test.cook")),
+                    position,
+                },
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+                context: None,
+            };
+            match client.request::<Completion>(params) {
+                Some(CompletionResponse::Array(items)) => items
+                    .into_iter()
+                    .map(|i| (i.label, i.insert_text))
+                    .collect(),
+                _ => Vec::new(),
+            }
+        };
+        assert_eq!(
+            complete(&mut client, Position::new(1, 5)),
+            vec![
+                ("eggs".to_string(), None),
+                ("flour".to_string(), None),
+                ("milk".to_string(), None)
+            ]
+        );
+        assert_eq!(
+            complete(&mut client, Position::new(0, 21)),
+            vec![
+                ("mixing bowl".to_string(), Some("mixing bowl{}".to_string())),
+                ("whisk".to_string(), None)
+            ]
+        );
+        assert_eq!(complete(&mut client, Position::new(0, 5)), Vec::new());
+        client.stop();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_close() {
+        let mut client = Client::start(None);
+        client.open(&uri("test.cook"), "Add @saffron{1%g} to the #paella pan{}");
+        client.open(&uri("other.cook"), "Add @");
+        let complete = |client: &mut Client| {
+            let params = CompletionParams {
+                text_document_position: TextDocumentPositionParams {
+                    text_document: TextDocumentIdentifier::new(uri("other.cook")),
+                    position: Position::new(0, 5),
+                },
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+                context: None,
+            };
+            match client.request::<Completion>(params) {
+                Some(CompletionResponse::Array(items)) => {
+                    items.into_iter().map(|i| i.label).collect()
+                }
+                _ => Vec::new(),
+            }
+        };
+        assert_eq!(complete(&mut client), vec!["saffron".to_string()]);
+
+        client.notify::<DidCloseTextDocument>(DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier::new(uri("test.cook")),
+        });
+        assert_eq!(client.diagnostics(), Vec::new());
+        assert_eq!(complete(&mut client), Vec::<String>::new());
+        let params = GotoDefinitionParams {
+            text_document_position_params: at("Add @saffron", "saffron"),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        assert_eq!(client.request::<GotoDefinition>(params), None);
+        client.stop();
+    }
+
+    #[test]
+    fn test_definition() {
+        let mut client = Client::start(None);
+        let text = "Top @./Pizza Dough{1} with @cheese{}, then more @&cheese{}";
+        client.open(&uri("test.cook"), text);
+        let definition = |client: &mut Client, needle| {
+            let params = GotoDefinitionParams {
+                text_document_position_params: at(text, needle),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            };
+            match client.request::<GotoDefinition>(params) {
+                Some(GotoDefinitionResponse::Scalar(location)) => Some(location),
+                _ => None,
+            }
+        };
+        assert_eq!(
+            definition(&mut client, "Dough").unwrap().uri,
+            uri("Pizza%20Dough.cook")
+        );
+        assert_eq!(
+            definition(&mut client, "&cheese").unwrap(),
+            Location::new(
+                uri("test.cook"),
+                Range::new(Position::new(0, 27), Position::new(0, 36))
+            )
+        );
+        assert_eq!(definition(&mut client, "@cheese"), None);
+        client.stop();
+    }
+
+    #[test]
+    fn test_symbols() {
+        let mut client = Client::start(None);
+        let text =
+            "Preheat the #oven.\n== Dough ==\nMix @flour{}\n\nKnead.\n= Sauce\nCook @tomatoes{}";
+        client.open(&uri("test.cook"), text);
+        let params = DocumentSymbolParams {
+            text_document: TextDocumentIdentifier::new(uri("test.cook")),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let Some(DocumentSymbolResponse::Nested(symbols)) =
+            client.request::<DocumentSymbolRequest>(params)
+        else {
+            panic!("expected nested symbols");
+        };
+        let symbols: Vec<_> = symbols
+            .into_iter()
+            .map(|s| (s.name, s.range, s.selection_range))
+            .collect();
+        assert_eq!(
+            symbols,
+            vec![
+                (
+                    "Dough".to_string(),
+                    Range::new(Position::new(1, 0), Position::new(4, 6)),
+                    Range::new(Position::new(1, 0), Position::new(1, 11))
+                ),
+                (
+                    "Sauce".to_string(),
+                    Range::new(Position::new(5, 0), Position::new(6, 16)),
+                    Range::new(Position::new(5, 0), Position::new(5, 7))
+                ),
+            ]
+        );
+        client.stop();
+    }
+}
@@ -1,60 +1,294 @@
 use std::collections::HashMap;
 
+use cooklang_rs::metadata::MetadataValue;
 use cooklang_rs::parser;
-use cooklang_rs::parser::Part;
+use cooklang_rs::parser::{Cookware, Ingredient, Note, Part, Quantity, Recipe, Text, Timer};
+use cooklang_rs::shopping;
+use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyDict, PyList, PyString};
 
-/// Formats the sum of two numbers as string.
-#[pyfunction]
-fn parse(text: String) -> Vec<Vec<HashMap<String, String>>> {
-    let r = parser::parse(text);
-    let mut out = Vec::new();
-    for l in r.into_iter() {
-        let mut out_line = Vec::new();
-        for p in l.into_iter() {
-            let mut i = HashMap::new();
-            match p {
-                Part::Metadata(metadata) => {
-                    i.insert("type".to_string(), "metadata".to_string());
-                    i.insert("key".to_string(), metadata.key);
-                    i.insert("value".to_string(), metadata.value);
-                    out_line.push(i)
-                }
-                Part::Cookware(cookware) => {
-                    i.insert("type".to_string(), "cookware".to_string());
-                    i.insert("name".to_string(), cookware.name);
-                    i.insert("quantity".to_string(), cookware.quantity);
-                    out_line.push(i)
-                }
-                Part::Timer(timer) => {
-                    i.insert("type".to_string(), "timer".to_string());
-                    i.insert("name".to_string(), timer.name);
-                    i.insert("quantity".to_string(), timer.quantity);
-                    i.insert("units".to_string(), timer.units);
-                    out_line.push(i)
-                }
-                Part::Ingredient(ingredient) => {
-                    i.insert("type".to_string(), "ingredient".to_string());
-                    i.insert("name".to_string(), ingredient.name);
-                    i.insert("quantity".to_string(), ingredient.quantity);
-                    i.insert("units".to_string(), ingredient.units);
-                    out_line.push(i)
-                }
-                Part::Text(string) => {
-                    i.insert("text".to_string(), string);
-                    out_line.push(i)
-                }
+fn cookware_to_dict(cookware: &Cookware) -> HashMap<String, String> {
+    let mut i = HashMap::new();
+    i.insert("type".to_string(), "cookware".to_string());
+    i.insert("name".to_string(), cookware.name.clone());
+    i.insert("quantity".to_string(), cookware.quantity.to_string());
+    i
+}
+
+fn timer_to_dict(timer: &Timer) -> HashMap<String, String> {
+    let mut i = HashMap::new();
+    i.insert("type".to_string(), "timer".to_string());
+    i.insert("name".to_string(), timer.name.clone());
+    i.insert("quantity".to_string(), timer.quantity.to_string());
+    i.insert("units".to_string(), timer.units.clone());
+    i
+}
+
+fn ingredient_to_dict(ingredient: &Ingredient) -> HashMap<String, String> {
+    let mut i = HashMap::new();
+    i.insert("type".to_string(), "ingredient".to_string());
+    i.insert("name".to_string(), ingredient.name.clone());
+    i.insert("quantity".to_string(), ingredient.quantity.to_string());
+    i.insert("units".to_string(), ingredient.units.clone());
+    for (flag, set) in [
+        ("fixed", ingredient.fixed),
+        ("optional", ingredient.optional),
+        ("hidden", ingredient.hidden),
+        ("reference", ingredient.reference),
+        ("recipe_link", ingredient.recipe_link),
+    ] {
+        if set {
+            i.insert(flag.to_string(), "true".to_string());
+        }
+    }
+    if !ingredient.note.is_empty() {
+        i.insert("note".to_string(), ingredient.note.clone());
+    }
+    i
+}
+
+fn part_to_dict(part: &Part) -> HashMap<String, String> {
+    match part {
+        Part::Cookware(cookware) => cookware_to_dict(cookware),
+        Part::Timer(timer) => timer_to_dict(timer),
+        Part::Ingredient(ingredient) => ingredient_to_dict(ingredient),
+        Part::Text(text) => HashMap::from([("text".to_string(), text.value.clone())]),
+        Part::Note(note) => HashMap::from([("note".to_string(), note.value.clone())]),
+    }
+}
+
+/// inverse of `part_to_dict`
+fn dict_to_part(mut d: HashMap<String, String>) -> PyResult<Part> {
+    if let Some(value) = d.remove("text") {
+        return Ok(Part::Text(Text {
+            value,
+            ..Default::default()
+        }));
+    }
+    // ingredients have a `note` too
+    if !d.contains_key("type") {
+        if let Some(value) = d.remove("note") {
+            return Ok(Part::Note(Note {
+                value,
+                ..Default::default()
+            }));
+        }
+    }
+    let mut take = |key: &str| d.remove(key).unwrap_or_default();
+    let part = match take("type").as_str() {
+        "ingredient" => Part::Ingredient(Ingredient {
+            name: take("name"),
+            quantity: Quantity::from(take("quantity").trim()),
+            units: take("units"),
+            fixed: take("fixed") == "true",
+            optional: take("optional") == "true",
+            hidden: take("hidden") == "true",
+            reference: take("reference") == "true",
+            recipe_link: take("recipe_link") == "true",
+            note: take("note"),
+            ..Default::default()
+        }),
+        "cookware" => Part::Cookware(Cookware {
+            name: take("name"),
+            quantity: Quantity::from(take("quantity").trim()),
+            ..Default::default()
+        }),
+        "timer" => Part::Timer(Timer {
+            name: take("name"),
+            quantity: Quantity::from(take("quantity").trim()),
+            units: take("units"),
+            ..Default::default()
+        }),
+        other => {
+            return Err(PyValueError::new_err(format!(
+                "unknown part type `{other}`"
+            )))
+        }
+    };
+    Ok(part)
+}
+
+/// Whole numbers become `int`, lists `list` and maps `dict`
+fn metadata_to_py(py: Python, value: &MetadataValue) -> PyObject {
+    match value {
+        MetadataValue::Null => py.None(),
+        MetadataValue::Bool(b) => b.to_object(py),
+        MetadataValue::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => (*n as i64).to_object(py),
+        MetadataValue::Number(n) => n.to_object(py),
+        MetadataValue::String(s) => s.to_object(py),
+        MetadataValue::List(values) => values
+            .iter()
+            .map(|v| metadata_to_py(py, v))
+            .collect::<Vec<_>>()
+            .to_object(py),
+        MetadataValue::Map(map) => {
+            let dict = PyDict::new(py);
+            for (key, value) in map {
+                dict.set_item(key, metadata_to_py(py, value)).unwrap();
             }
+            dict.to_object(py)
         }
-        out.push(out_line);
     }
+}
 
-    out
+/// inverse of `metadata_to_py`
+fn py_to_metadata(value: &PyAny) -> PyResult<MetadataValue> {
+    Ok(if value.is_none() {
+        MetadataValue::Null
+    } else if let Ok(b) = value.downcast::<PyBool>() {
+        MetadataValue::Bool(b.is_true())
+    } else if let Ok(s) = value.downcast::<PyString>() {
+        MetadataValue::String(s.to_str()?.to_string())
+    } else if let Ok(list) = value.downcast::<PyList>() {
+        MetadataValue::List(list.iter().map(py_to_metadata).collect::<PyResult<_>>()?)
+    } else if let Ok(dict) = value.downcast::<PyDict>() {
+        MetadataValue::Map(
+            dict.iter()
+                .map(|(k, v)| Ok((k.str()?.to_str()?.to_string(), py_to_metadata(v)?)))
+                .collect::<PyResult<_>>()?,
+        )
+    } else {
+        MetadataValue::Number(value.extract()?)
+    })
+}
+
+/// Read back a recipe returned by `parse`
+fn dict_to_recipe(recipe: &PyDict) -> PyResult<Recipe> {
+    let get = |key: &str| {
+        recipe
+            .get_item(key)
+            .ok_or_else(|| PyValueError::new_err(format!("recipe has no `{key}`")))
+    };
+    let mut r = Recipe::default();
+    for (key, value) in get("metadata")?.downcast::<PyDict>()?.iter() {
+        r.metadata.insert(key.extract()?, py_to_metadata(value)?);
+    }
+    for parts in get("steps")?.extract::<Vec<Vec<HashMap<String, String>>>>()? {
+        r.steps.push(parser::Step {
+            parts: parts
+                .into_iter()
+                .map(dict_to_part)
+                .collect::<PyResult<_>>()?,
+            ..Default::default()
+        });
+    }
+    if let Some(sections) = recipe.get_item("sections") {
+        for section in sections.extract::<Vec<&PyDict>>()? {
+            let name = section
+                .get_item("name")
+                .map_or(Ok(String::new()), |n| n.extract())?;
+            let paragraphs = match section.get_item("paragraphs") {
+                Some(p) => p.extract::<Vec<Vec<usize>>>()?,
+                None => Vec::new(),
+            };
+            r.sections.push(parser::Section {
+                name,
+                paragraphs: paragraphs
+                    .iter()
+                    .filter_map(|steps| Some(*steps.first()?..*steps.last()? + 1))
+                    .collect(),
+                ..Default::default()
+            });
+        }
+    }
+    Ok(r)
+}
+
+fn recipe_to_dict(py: Python, r: &Recipe) -> PyResult<PyObject> {
+    let metadata = PyDict::new(py);
+    for (key, value) in r.metadata.iter() {
+        metadata.set_item(key, metadata_to_py(py, value))?;
+    }
+    let steps: Vec<Vec<HashMap<String, String>>> = r
+        .steps
+        .iter()
+        .map(|step| step.parts.iter().map(part_to_dict).collect())
+        .collect();
+    let ingredients: Vec<_> = r
+        .ingredients()
+        .into_iter()
+        .map(ingredient_to_dict)
+        .collect();
+    let cookware: Vec<_> = r.cookware().into_iter().map(cookware_to_dict).collect();
+    let timers: Vec<_> = r.timers().into_iter().map(timer_to_dict).collect();
+    let sections = PyList::empty(py);
+    for section in &r.sections {
+        let paragraphs: Vec<Vec<usize>> = section
+            .paragraphs
+            .iter()
+            .map(|steps| steps.clone().collect())
+            .collect();
+        let s = PyDict::new(py);
+        s.set_item("name", &section.name)?;
+        s.set_item("paragraphs", paragraphs)?;
+        sections.append(s)?;
+    }
+
+    let out = PyDict::new(py);
+    out.set_item("metadata", metadata)?;
+    out.set_item("steps", steps)?;
+    out.set_item("ingredients", ingredients)?;
+    out.set_item("cookware", cookware)?;
+    out.set_item("timers", timers)?;
+    out.set_item("sections", sections)?;
+    Ok(out.into())
+}
+
+/// Parse a recipe, raising `ValueError` if the text is not valid cooklang, and a
+/// `UserWarning` for every conflict between the front matter and `>>` metadata.
+///
+/// Steps are lists of parts: `{"text": ...}`, `{"note": ...}` for `> text` lines that are
+/// alone in their step, or dicts with a `type` for the items.
+///
+/// The recipe is a dict with `metadata`, `steps`, `ingredients`, `cookware`, `timers`
+/// and `sections`. A section is a dict with a `name` and its `paragraphs`, lists of
+/// indices in `steps`.
+#[pyfunction]
+fn parse(py: Python, text: String) -> PyResult<PyObject> {
+    let r = parser::parse(text).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let user_warning = py.import("builtins")?.getattr("UserWarning")?;
+    for warning in &r.warnings {
+        PyErr::warn(py, user_warning, &warning.to_string(), 1)?;
+    }
+    recipe_to_dict(py, &r)
+}
+
+/// Scale a recipe returned by `parse` from its `servings` metadata to `servings`.
+#[pyfunction]
+fn scale(py: Python, recipe: &PyDict, servings: u32) -> PyResult<PyObject> {
+    let mut r = dict_to_recipe(recipe)?;
+    r.scale_to_servings(servings)
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    recipe_to_dict(py, &r)
+}
+
+/// Sum the ingredients of recipes returned by `parse`.
+///
+/// Items are dicts with `name`, `quantity` and `units`, sorted by name then units.
+#[pyfunction]
+fn shopping_list(recipes: Vec<&PyDict>) -> PyResult<Vec<HashMap<String, String>>> {
+    let recipes = recipes
+        .into_iter()
+        .map(dict_to_recipe)
+        .collect::<PyResult<Vec<_>>>()?;
+    Ok(shopping::shopping_list(&recipes)
+        .into_iter()
+        .map(|item| {
+            HashMap::from([
+                ("name".to_string(), item.name),
+                ("quantity".to_string(), item.quantity.to_string()),
+                ("units".to_string(), item.units),
+            ])
+        })
+        .collect())
 }
 
 /// A Python module implemented in Rust.
 #[pymodule]
 fn cooklang(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(parse, m)?)?;
+    m.add_function(wrap_pyfunction!(scale, m)?)?;
+    m.add_function(wrap_pyfunction!(shopping_list, m)?)?;
     Ok(())
 }
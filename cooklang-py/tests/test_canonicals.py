@@ -2,7 +2,7 @@ import unittest
 from pathlib import Path
 
 import yaml
-from cooklang import parse
+from cooklang import parse, scale, shopping_list
 
 CANONICAL_TESTS_FILE = Path(__file__).parent / "canonical.yaml"
 
@@ -15,49 +15,116 @@ class TestCanonical(unittest.TestCase):
             result = test["result"]
             cooklang_result = parse(test["source"])
 
-            n_metadata_cooklang = 0
-            line_index = 0
-            for r in cooklang_result:
-                if len(r) == 1 and "type" in r[0] and r[0]["type"] == "metadata":
-                    # metadata
-                    n_metadata_cooklang += 1
-                    name = r[0]["key"]
-                    self.assertTrue(name in result["metadata"])
-                    self.assertEqual(result["metadata"][name], r[0]["value"])
-                else:
-                    # parser don't output empty text, canonical does: remove empty text from canonical
-                    canonical_steps = [
-                        e_canonical
-                        for e_canonical in result["steps"][line_index]
-                        if not (
-                            "type" in e_canonical
-                            and "value" in e_canonical
-                            and e_canonical["type"] == "text"
-                            and e_canonical["value"].strip() == ""
-                        )
-                    ]
-
-                    self.assertEqual(len(canonical_steps), len(r))
-                    for e_canonical, e_parser in zip(canonical_steps, r):
-                        if e_canonical["type"] == "text":
-                            self.assertTrue("text" in e_parser)
-                            self.assertEqual(e_parser["text"], e_canonical["value"].strip())
-                        else:
-                            # quantity is not managed the same way between canonical and parser
-                            # - if quantity is not a string in canonical, transform it to string
-                            # - if quantity is a default value, remove the default value
-                            # - if quantity is a frac, then change it to string representation
-                            if "quantity" in e_canonical:
-                                e_canonical["quantity"] = str(e_canonical["quantity"])
-                                if e_parser["quantity"] == "":
-                                    self.assertIn(e_canonical["quantity"], ["1", "some"])
-                                    e_canonical["quantity"] = ""
-                                if e_parser["quantity"] != e_canonical["quantity"]:
-                                    # then probably fraction
-                                    self.assertEqual(eval(e_parser["quantity"]), eval(e_canonical["quantity"]))
-                                    e_canonical["quantity"] = e_parser["quantity"]
-                            self.assertEqual(e_canonical, e_parser)
-
-                    line_index += 1
-            self.assertEqual(line_index, len(result["steps"]))
-            self.assertEqual(n_metadata_cooklang, len(result["metadata"]))
+            self.assertEqual(cooklang_result["metadata"], {k: str(v) for k, v in result["metadata"].items()})
+            self.assertEqual(len(cooklang_result["steps"]), len(result["steps"]))
+
+            for r, canonical_line in zip(cooklang_result["steps"], result["steps"]):
+                # parser don't output empty text, canonical does: remove empty text from canonical
+                canonical_steps = [
+                    e_canonical
+                    for e_canonical in canonical_line
+                    if not (
+                        "type" in e_canonical
+                        and "value" in e_canonical
+                        and e_canonical["type"] == "text"
+                        and e_canonical["value"].strip() == ""
+                    )
+                ]
+
+                self.assertEqual(len(canonical_steps), len(r))
+                for e_canonical, e_parser in zip(canonical_steps, r):
+                    if e_canonical["type"] == "text":
+                        self.assertTrue("text" in e_parser)
+                        self.assertEqual(e_parser["text"], e_canonical["value"].strip())
+                    else:
+                        # quantity is not managed the same way between canonical and parser
+                        # - if quantity is not a string in canonical, transform it to string
+                        # - if quantity is a default value, remove the default value
+                        # - if quantity is a frac, then change it to string representation
+                        if "quantity" in e_canonical:
+                            e_canonical["quantity"] = str(e_canonical["quantity"])
+                            if e_parser["quantity"] == "":
+                                self.assertIn(e_canonical["quantity"], ["1", "some"])
+                                e_canonical["quantity"] = ""
+                            if e_parser["quantity"] != e_canonical["quantity"]:
+                                # then probably fraction
+                                self.assertEqual(eval(e_parser["quantity"]), eval(e_canonical["quantity"]))
+                                e_canonical["quantity"] = e_parser["quantity"]
+                        self.assertEqual(e_canonical, e_parser)
+
+    def test_collections(self) -> None:
+        recipe = parse("Boil @water{1%l} in #pot\nAdd @salt and @water{500%ml} to the #pot")
+        self.assertEqual([i["name"] for i in recipe["ingredients"]], ["water", "salt"])
+        self.assertEqual([c["name"] for c in recipe["cookware"]], ["pot"])
+        self.assertEqual(recipe["timers"], [])
+
+    def test_sections(self) -> None:
+        recipe = parse("Preheat the #oven\n\n== Dough ==\nMix @flour{500%g}\nand @water\n\nKnead")
+        self.assertEqual(
+            recipe["sections"],
+            [
+                {"name": "", "paragraphs": [[0]]},
+                {"name": "Dough", "paragraphs": [[1, 2], [3]]},
+            ],
+        )
+        self.assertEqual(scale(recipe | {"metadata": {"servings": "1"}}, 2)["sections"], recipe["sections"])
+
+    def test_notes(self) -> None:
+        recipe = parse("> Don't burn the roux!\nStir the @roux")
+        self.assertEqual(recipe["steps"][0], [{"note": "Don't burn the roux!"}])
+        self.assertEqual(scale(recipe | {"metadata": {"servings": "1"}}, 2)["steps"], recipe["steps"])
+
+    def test_front_matter(self) -> None:
+        source = "---\ntitle: Bread\nservings: 4\ntags: [baking, easy]\n---\n>> servings: 6\nKnead @flour{500%g}"
+        with self.assertWarns(UserWarning):
+            recipe = parse(source)
+        self.assertEqual(recipe["metadata"], {"title": "Bread", "servings": 4, "tags": ["baking", "easy"]})
+        self.assertEqual(scale(recipe, 8)["metadata"]["servings"], 8)
+
+    def test_ingredient_note(self) -> None:
+        recipe = parse("Fry @onion{1}(finely chopped) and @onion{1}(sliced)")
+        self.assertEqual(
+            recipe["steps"][0][1],
+            {"type": "ingredient", "name": "onion", "quantity": "1", "units": "", "note": "finely chopped"},
+        )
+        self.assertEqual(shopping_list([recipe]), [{"name": "onion", "quantity": "2", "units": ""}])
+        self.assertEqual(scale(recipe | {"metadata": {"servings": "1"}}, 2)["steps"][0][1]["note"], "finely chopped")
+
+    def test_modifiers(self) -> None:
+        recipe = parse("Mix @?parsley, @-salt, @flour{500%g}, @&flour{100%g} and @./sauces/Pesto{}")
+        flags = [
+            {k: v for k, v in part.items() if v == "true"}
+            for part in recipe["steps"][0]
+            if part.get("type") == "ingredient"
+        ]
+        self.assertEqual(
+            flags,
+            [{"optional": "true"}, {"hidden": "true"}, {}, {"reference": "true"}, {"recipe_link": "true"}],
+        )
+        self.assertEqual([i["name"] for i in recipe["ingredients"]], ["parsley", "flour", "./sauces/Pesto"])
+        with self.assertRaises(ValueError):
+            parse("Add @&flour")
+
+    def test_parse_error(self) -> None:
+        with self.assertRaises(ValueError):
+            parse("Add @flour{250%g")
+
+    def test_scale(self) -> None:
+        recipe = parse(">> servings: 4\nMix @flour{500%g}, @milk{1/2%l} and @salt{=1%tsp} for ~{10%min}")
+        scaled = scale(recipe, 6)
+        self.assertEqual(scaled["metadata"], {"servings": "6"})
+        self.assertEqual([i["quantity"] for i in scaled["ingredients"]], ["750", "3/4", "1"])
+        self.assertEqual(scaled["timers"][0]["quantity"], "10")
+
+    def test_shopping_list(self) -> None:
+        pancakes = parse("Mix @flour{250%g}, @milk{1/2%l} and @salt")
+        bread = parse("Knead @flour{1%kg} with @salt{a pinch}.\nBrush with @milk{1/4%l}")
+        self.assertEqual(
+            shopping_list([pancakes, bread]),
+            [
+                {"name": "flour", "quantity": "1250", "units": "g"},
+                {"name": "milk", "quantity": "3/4", "units": "l"},
+                {"name": "salt", "quantity": "", "units": ""},
+                {"name": "salt", "quantity": "a pinch", "units": ""},
+            ],
+        )
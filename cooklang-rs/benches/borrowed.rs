@@ -0,0 +1,64 @@
+//! Time and allocations of `parse` and `parse_borrowed`.
+//!
+//! The allocations of one parse of each recipe are printed before the
+//! timings.
+
+mod corpus;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use cooklang_rs::parser::{parse, parse_borrowed};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// the system allocator, counting allocations
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for steps in [10, 1000] {
+        let source = corpus::recipe(0, steps);
+        let input = source.clone();
+        let owned = allocations(|| drop(parse(input).unwrap()));
+        let borrowed = allocations(|| drop(parse_borrowed(&source).unwrap()));
+        println!("{steps} steps: {owned} allocations owned, {borrowed} borrowed");
+
+        group.bench_with_input(BenchmarkId::new("owned", steps), &source, |b, s| {
+            b.iter(|| parse(black_box(s.clone())))
+        });
+        group.bench_with_input(BenchmarkId::new("borrowed", steps), &source, |b, s| {
+            b.iter(|| parse_borrowed(black_box(s)).map(drop))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
@@ -0,0 +1,142 @@
+//! Synthetic recipes for the benchmarks.
+//!
+//! Recipes are generated from a seed with a fixed pseudo-random sequence, so
+//! that every commit is measured on the same text. They use every construct
+//! of the language in realistic proportions: metadata, sections, notes,
+//! comments, ingredients with amounts, modifiers and notes, cookware,
+//! timers, and non ASCII text.
+
+// each benchmark uses a part of the generator
+#![allow(dead_code)]
+
+const INGREDIENTS: &[&str] = &[
+    "flour",
+    "sea salt",
+    "olive oil",
+    "eggs",
+    "unsalted butter",
+    "crème fraîche",
+    "garlic",
+    "red onion",
+    "tomatoes",
+    "fresh basil",
+    "brown sugar",
+    "whole milk",
+    "piment d'Espelette",
+    "🧂",
+];
+const UNITS: &[&str] = &["g", "kg", "ml", "l", "tsp", "tbsp", "cups", "pinch", ""];
+const QUANTITIES: &[&str] = &["1", "2", "250", "1/2", "0.5", "3", "=1", "a few", "12"];
+const COOKWARE: &[&str] = &["bowl", "frying pan", "pot", "oven", "baking sheet", "whisk"];
+const WORDS: &[&str] = &[
+    "mix", "stir", "gently", "until", "smooth", "then", "add", "the", "and", "with", "a", "bit",
+    "more", "cover", "leave", "to", "rest", "golden", "brown", "fold", "in", "déjà", "chaud",
+];
+
+/// xorshift, deterministic and good enough to pick words
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.below(items.len())]
+    }
+
+    /// true once every `n` times on average
+    fn one_in(&mut self, n: usize) -> bool {
+        self.below(n) == 0
+    }
+}
+
+fn words(random: &mut Random, out: &mut String) {
+    for _ in 0..1 + random.below(6) {
+        out.push(' ');
+        out.push_str(random.pick(WORDS));
+    }
+}
+
+fn step(random: &mut Random, out: &mut String) {
+    out.push_str(random.pick(&["Mix", "Add", "Cook", "Bake", "Whisk"]));
+    for _ in 0..1 + random.below(4) {
+        words(random, out);
+        out.push(' ');
+        match random.below(5) {
+            0 => {
+                out.push('#');
+                out.push_str(random.pick(COOKWARE));
+                out.push_str("{}");
+            }
+            1 => {
+                let units = random.pick(&["minutes", "hours", "s"]);
+                out.push_str(&format!("~{{{}%{units}}}", 1 + random.below(90)));
+            }
+            _ => {
+                out.push('@');
+                if random.one_in(8) {
+                    out.push_str(random.pick(&["?", "-"]));
+                }
+                out.push_str(random.pick(INGREDIENTS));
+                let units = random.pick(UNITS);
+                match units {
+                    "" => out.push_str(&format!("{{{}}}", random.pick(QUANTITIES))),
+                    _ => out.push_str(&format!("{{{}%{units}}}", random.pick(QUANTITIES))),
+                }
+                if random.one_in(6) {
+                    out.push_str("(finely chopped)");
+                }
+            }
+        }
+        if random.one_in(10) {
+            out.push_str(" [- or more -]");
+        }
+    }
+    words(random, out);
+    out.push('.');
+    if random.one_in(5) {
+        out.push_str(" -- adjust to taste");
+    }
+    out.push('\n');
+}
+
+/// A recipe of `steps` steps, the same for the same `seed`
+pub fn recipe(seed: u64, steps: usize) -> String {
+    let mut random = Random(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1);
+    let mut out = String::new();
+    if random.one_in(3) {
+        out.push_str("---\ntitle: Generated\ntags: [bench, synthetic]\n---\n");
+    }
+    out.push_str(&format!(
+        ">> servings: {}\n>> source: https://example.com/{seed}\n\n",
+        1 + random.below(8)
+    ));
+    for i in 0..steps {
+        if i % 12 == 0 && i > 0 {
+            out.push_str(&format!("\n== Part {} ==\n", i / 12));
+        }
+        match random.below(20) {
+            0 => out.push_str("> Keep everything at room temperature.\n"),
+            1 => out.push_str("-- a comment on its own line\n"),
+            2 => out.push('\n'),
+            _ => {}
+        }
+        step(&mut random, &mut out);
+    }
+    out
+}
+
+/// `count` recipes of 3 to 30 steps
+pub fn corpus(count: usize) -> Vec<String> {
+    (0..count as u64)
+        .map(|seed| recipe(seed, 3 + (seed as usize * 7) % 28))
+        .collect()
+}
@@ -0,0 +1,105 @@
+//! Throughput of the parser on the synthetic corpus and on large recipes.
+//!
+//! The corpus is deterministic, so results can be compared across commits:
+//!
+//! ```sh
+//! cargo bench --bench parse -- --save-baseline before
+//! # change the parser
+//! cargo bench --bench parse -- --baseline before
+//! ```
+
+mod corpus;
+
+use cooklang_rs::parser::{parse, parse_recovering, remove_comment};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+/// recipes of the corpus
+const RECIPES: usize = 200;
+/// steps of the large recipes
+const LARGE: [usize; 2] = [1000, 5000];
+
+fn bench_parse(c: &mut Criterion) {
+    let corpus = corpus::corpus(RECIPES);
+    let bytes: usize = corpus.iter().map(String::len).sum();
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Bytes(bytes as u64));
+    group.bench_function("corpus", |b| {
+        b.iter(|| {
+            for source in &corpus {
+                parse(black_box(source.clone())).unwrap();
+            }
+        })
+    });
+    for steps in LARGE {
+        let source = corpus::recipe(0, steps);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::new("large", steps), &source, |b, s| {
+            b.iter(|| parse(black_box(s.clone())).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// steps opening a `[-` comment without closing it, which the parser must
+/// report without scanning the rest of the recipe from every one of them
+fn bench_unclosed_comments(c: &mut Criterion) {
+    let mut group = c.benchmark_group("unclosed comments");
+    for steps in LARGE {
+        let source = "Stir @salt{1%g} [- gently\n".repeat(steps);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::new("parse", steps), &source, |b, s| {
+            b.iter(|| parse(black_box(s.clone())).unwrap_err())
+        });
+        group.bench_with_input(
+            BenchmarkId::new("parse_recovering", steps),
+            &source,
+            |b, s| b.iter(|| parse_recovering(black_box(s))),
+        );
+    }
+    group.finish();
+}
+
+fn bench_error_lines(c: &mut Criterion) {
+    let mut group = c.benchmark_group("error lines");
+    for steps in LARGE {
+        let source = "Add @{1} to the #pot\n".repeat(steps);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::new("parse_recovering", steps),
+            &source,
+            |b, s| b.iter(|| parse_recovering(black_box(s))),
+        );
+    }
+    group.finish();
+}
+
+fn bench_remove_comment(c: &mut Criterion) {
+    let corpus = corpus::corpus(RECIPES);
+    let bytes: usize = corpus.iter().map(String::len).sum();
+    let mut group = c.benchmark_group("remove_comment");
+    group.throughput(Throughput::Bytes(bytes as u64));
+    group.bench_function("corpus", |b| {
+        b.iter(|| {
+            for source in &corpus {
+                remove_comment(black_box(source.clone())).unwrap();
+            }
+        })
+    });
+    for steps in LARGE {
+        let source = corpus::recipe(0, steps);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::new("large", steps), &source, |b, s| {
+            b.iter(|| remove_comment(black_box(s.clone())))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_parse,
+    bench_unclosed_comments,
+    bench_error_lines,
+    bench_remove_comment
+);
+criterion_main!(benches);
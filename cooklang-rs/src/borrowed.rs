@@ -0,0 +1,330 @@
+//! Recipes borrowing their text from the parsed source.
+//!
+//! [`parse_borrowed`] reads a recipe without copying its text: names, units,
+//! notes and texts are slices of the source. Only texts, notes and section
+//! names joined across comments, the metadata and the warnings are owned. The types mirror the
+//! ones of [`parser`](crate::parser), and `into_owned` converts them.
+//!
+//! [`parse_borrowed`]: crate::parser::parse_borrowed
+
+use std::borrow::Cow;
+use std::ops::Range;
+
+use indexmap::IndexMap;
+
+use crate::metadata::MetadataValue;
+use crate::parser::{
+    number, Cookware, Ingredient, Note, Part, Quantity, Recipe, Section, Span, Step, Text, Timer,
+    Warning,
+};
+
+/// A [`Quantity`] with its free text borrowed
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum QuantityRef<'a> {
+    #[default]
+    Empty,
+    Integer(u64),
+    Decimal(f64),
+    Fraction(u64, u64),
+    Text(&'a str),
+}
+
+impl<'a> From<&'a str> for QuantityRef<'a> {
+    /// Read a trimmed quantity, falling back to text when it is not a number
+    fn from(s: &'a str) -> Self {
+        if s.is_empty() {
+            return QuantityRef::Empty;
+        }
+        match number(s) {
+            Ok((_, quantity)) => quantity,
+            Err(_) => QuantityRef::Text(s),
+        }
+    }
+}
+
+impl QuantityRef<'_> {
+    pub fn into_owned(self) -> Quantity {
+        match self {
+            QuantityRef::Empty => Quantity::Empty,
+            QuantityRef::Integer(i) => Quantity::Integer(i),
+            QuantityRef::Decimal(d) => Quantity::Decimal(d),
+            QuantityRef::Fraction(n, d) => Quantity::Fraction(n, d),
+            QuantityRef::Text(s) => Quantity::Text(s.to_string()),
+        }
+    }
+}
+
+impl Quantity {
+    pub(crate) fn as_borrowed(&self) -> QuantityRef<'_> {
+        match self {
+            Quantity::Empty => QuantityRef::Empty,
+            Quantity::Integer(i) => QuantityRef::Integer(*i),
+            Quantity::Decimal(d) => QuantityRef::Decimal(*d),
+            Quantity::Fraction(n, d) => QuantityRef::Fraction(*n, *d),
+            Quantity::Text(s) => QuantityRef::Text(s),
+        }
+    }
+}
+
+/// An [`Ingredient`] borrowing its name, units and note
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct IngredientRef<'a> {
+    pub name: &'a str,
+    pub quantity: QuantityRef<'a>,
+    pub units: &'a str,
+    pub fixed: bool,
+    pub optional: bool,
+    pub hidden: bool,
+    pub reference: bool,
+    pub recipe_link: bool,
+    pub note: &'a str,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CookwareRef<'a> {
+    pub name: &'a str,
+    pub quantity: QuantityRef<'a>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TimerRef<'a> {
+    pub name: &'a str,
+    pub quantity: QuantityRef<'a>,
+    pub units: &'a str,
+    pub span: Span,
+}
+
+/// A [`Text`], owned when it joins texts separated by comments
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TextRef<'a> {
+    pub value: Cow<'a, str>,
+    pub span: Span,
+}
+
+/// A [`Note`], owned when block comments are cut out of it
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct NoteRef<'a> {
+    pub value: Cow<'a, str>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PartRef<'a> {
+    Cookware(CookwareRef<'a>),
+    Timer(TimerRef<'a>),
+    Ingredient(IngredientRef<'a>),
+    Text(TextRef<'a>),
+    /// alone in its step
+    Note(NoteRef<'a>),
+}
+
+impl PartRef<'_> {
+    pub fn span(&self) -> Span {
+        match self {
+            PartRef::Cookware(c) => c.span,
+            PartRef::Timer(t) => t.span,
+            PartRef::Ingredient(i) => i.span,
+            PartRef::Text(t) => t.span,
+            PartRef::Note(n) => n.span,
+        }
+    }
+
+    pub(crate) fn set_span(&mut self, span: Span) {
+        match self {
+            PartRef::Cookware(c) => c.span = span,
+            PartRef::Timer(t) => t.span = span,
+            PartRef::Ingredient(i) => i.span = span,
+            PartRef::Text(t) => t.span = span,
+            PartRef::Note(n) => n.span = span,
+        }
+    }
+
+    pub fn into_owned(self) -> Part {
+        match self {
+            PartRef::Cookware(c) => Part::Cookware(Cookware {
+                name: c.name.to_string(),
+                quantity: c.quantity.into_owned(),
+                span: c.span,
+            }),
+            PartRef::Timer(t) => Part::Timer(Timer {
+                name: t.name.to_string(),
+                quantity: t.quantity.into_owned(),
+                units: t.units.to_string(),
+                span: t.span,
+            }),
+            PartRef::Ingredient(i) => Part::Ingredient(Ingredient {
+                name: i.name.to_string(),
+                quantity: i.quantity.into_owned(),
+                units: i.units.to_string(),
+                fixed: i.fixed,
+                optional: i.optional,
+                hidden: i.hidden,
+                reference: i.reference,
+                recipe_link: i.recipe_link,
+                note: i.note.to_string(),
+                span: i.span,
+            }),
+            PartRef::Text(t) => Part::Text(Text {
+                value: t.value.into_owned(),
+                span: t.span,
+            }),
+            PartRef::Note(n) => Part::Note(Note {
+                value: n.value.into_owned(),
+                span: n.span,
+            }),
+        }
+    }
+}
+
+impl Part {
+    pub(crate) fn as_borrowed(&self) -> PartRef<'_> {
+        match self {
+            Part::Cookware(c) => PartRef::Cookware(CookwareRef {
+                name: &c.name,
+                quantity: c.quantity.as_borrowed(),
+                span: c.span,
+            }),
+            Part::Timer(t) => PartRef::Timer(TimerRef {
+                name: &t.name,
+                quantity: t.quantity.as_borrowed(),
+                units: &t.units,
+                span: t.span,
+            }),
+            Part::Ingredient(i) => PartRef::Ingredient(IngredientRef {
+                name: &i.name,
+                quantity: i.quantity.as_borrowed(),
+                units: &i.units,
+                fixed: i.fixed,
+                optional: i.optional,
+                hidden: i.hidden,
+                reference: i.reference,
+                recipe_link: i.recipe_link,
+                note: &i.note,
+                span: i.span,
+            }),
+            Part::Text(t) => PartRef::Text(TextRef {
+                value: Cow::Borrowed(&t.value),
+                span: t.span,
+            }),
+            Part::Note(n) => PartRef::Note(NoteRef {
+                value: Cow::Borrowed(&n.value),
+                span: n.span,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StepRef<'a> {
+    pub parts: Vec<PartRef<'a>>,
+    pub span: Span,
+}
+
+impl StepRef<'_> {
+    pub fn into_owned(self) -> Step {
+        Step {
+            parts: self.parts.into_iter().map(PartRef::into_owned).collect(),
+            span: self.span,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SectionRef<'a> {
+    /// owned when block comments are cut out of it
+    pub name: Cow<'a, str>,
+    pub paragraphs: Vec<Range<usize>>,
+    pub span: Span,
+}
+
+impl SectionRef<'_> {
+    pub fn into_owned(self) -> Section {
+        Section {
+            name: self.name.into_owned(),
+            paragraphs: self.paragraphs,
+            span: self.span,
+        }
+    }
+}
+
+/// A [`Recipe`] borrowing from its source
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RecipeRef<'a> {
+    /// metadata are few, and typed, they are owned
+    pub metadata: IndexMap<String, MetadataValue>,
+    pub steps: Vec<StepRef<'a>>,
+    pub sections: Vec<SectionRef<'a>>,
+    pub warnings: Vec<Warning>,
+}
+
+impl RecipeRef<'_> {
+    pub fn into_owned(self) -> Recipe {
+        Recipe {
+            metadata: self.metadata,
+            steps: self.steps.into_iter().map(StepRef::into_owned).collect(),
+            sections: self
+                .sections
+                .into_iter()
+                .map(SectionRef::into_owned)
+                .collect(),
+            warnings: self.warnings,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse, parse_borrowed, Expected};
+    use pretty_assertions::assert_eq;
+
+    /// whether `s` is a slice of `source`
+    fn borrows(source: &str, s: &str) -> bool {
+        source.as_bytes().as_ptr_range().contains(&s.as_ptr())
+    }
+
+    #[test]
+    fn test_parse_borrowed() {
+        let source = ">> servings: 2\n== Dough ==\nMix @flour{500%g}(sifted) in a #large bowl{1}\n\
+            Wait [- a bit -] then  ~rest{ a few %hours}\n> Cover it";
+        let recipe = parse_borrowed(source).unwrap();
+        let mut texts = Vec::new();
+        for part in recipe.steps.iter().flat_map(|s| &s.parts) {
+            match part {
+                PartRef::Ingredient(i) => {
+                    assert!(borrows(source, i.name) && borrows(source, i.units));
+                    assert_eq!((i.name, i.note), ("flour", "sifted"));
+                }
+                PartRef::Cookware(c) => assert!(borrows(source, c.name)),
+                PartRef::Timer(t) => {
+                    assert_eq!(t.quantity, QuantityRef::Text("a few"));
+                    assert!(matches!(t.quantity, QuantityRef::Text(q) if borrows(source, q)));
+                }
+                PartRef::Text(t) => texts.push(t.value.clone()),
+                PartRef::Note(n) => {
+                    assert!(matches!(&n.value, Cow::Borrowed(v) if borrows(source, v)))
+                }
+            }
+        }
+        assert!(matches!(&texts[0], Cow::Borrowed(t) if borrows(source, t)));
+        // texts joined across a comment are owned
+        assert_eq!(texts[2], Cow::<str>::Owned("Wait  then".to_string()));
+        assert_eq!(recipe.sections[0].name, "Dough");
+
+        assert_eq!(
+            recipe.into_owned(),
+            parse(source.to_string()).unwrap(),
+            "the same recipe as `parse`"
+        );
+        assert_eq!(
+            parse_borrowed("Add @&salt{}").unwrap_err().expected,
+            Expected::Definition
+        );
+        assert_eq!(
+            parse_borrowed("Add @salt{"),
+            Err(parse("Add @salt{".to_string()).unwrap_err())
+        );
+    }
+}
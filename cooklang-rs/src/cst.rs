@@ -0,0 +1,543 @@
+//! Lossless concrete syntax tree of a recipe.
+//!
+//! The tree is the sequence of the tokens of the source: the semantic
+//! elements, as parsed, and the comments, spaces and line breaks between
+//! them. Writing the text of every token back gives the source unchanged, so
+//! tools can edit a recipe without losing its layout. [`parse`] builds the
+//! [`Recipe`] from this tree.
+//!
+//! [`parse`]: crate::parser::parse
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
+
+use indexmap::IndexMap;
+
+use crate::borrowed::{PartRef, RecipeRef, SectionRef, StepRef};
+use crate::metadata::MetadataValue;
+use crate::parser::{
+    comment, lines, Expected, Line, Locator, Metadata, ParseError, Part, Recipe, Span, Warning,
+};
+
+/// What a token is
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    /// the YAML between `---` lines, with its values
+    FrontMatter(IndexMap<String, MetadataValue>),
+    /// a `>> key: value` line
+    Metadata(Metadata),
+    /// a `== name ==` header, with its name
+    Section(String),
+    /// an ingredient, a cookware, a timer, a text or a note
+    Part(Part),
+    /// a `-- line` or `[- block -]` comment
+    Comment,
+    /// spaces and tabs
+    Whitespace,
+    /// a line break, `\n` or `\r\n`
+    Newline,
+    /// text skipped by [`SyntaxTree::parse_recovering`] after an error
+    Error,
+}
+
+impl TokenKind {
+    fn as_borrowed(&self) -> KindRef<'_> {
+        match self {
+            TokenKind::FrontMatter(values) => KindRef::FrontMatter(Cow::Borrowed(values)),
+            TokenKind::Metadata(m) => KindRef::Metadata(Cow::Borrowed(m)),
+            TokenKind::Section(name) => KindRef::Section(Cow::Borrowed(name)),
+            TokenKind::Part(part) => KindRef::Part(part.as_borrowed()),
+            TokenKind::Comment => KindRef::Comment,
+            TokenKind::Whitespace => KindRef::Whitespace,
+            TokenKind::Newline => KindRef::Newline,
+            TokenKind::Error => KindRef::Error,
+        }
+    }
+}
+
+/// A [`TokenKind`] borrowing from the source, or from a token
+pub(crate) enum KindRef<'a> {
+    FrontMatter(Cow<'a, IndexMap<String, MetadataValue>>),
+    Metadata(Cow<'a, Metadata>),
+    Section(Cow<'a, str>),
+    Part(PartRef<'a>),
+    Comment,
+    Whitespace,
+    Newline,
+    Error,
+}
+
+impl KindRef<'_> {
+    fn into_owned(self) -> TokenKind {
+        match self {
+            KindRef::FrontMatter(values) => TokenKind::FrontMatter(values.into_owned()),
+            KindRef::Metadata(m) => TokenKind::Metadata(m.into_owned()),
+            KindRef::Section(name) => TokenKind::Section(name.into_owned()),
+            KindRef::Part(part) => TokenKind::Part(part.into_owned()),
+            KindRef::Comment => TokenKind::Comment,
+            KindRef::Whitespace => TokenKind::Whitespace,
+            KindRef::Newline => TokenKind::Newline,
+            KindRef::Error => TokenKind::Error,
+        }
+    }
+}
+
+/// A piece of the source
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// the source text of the token, as written
+    pub text: String,
+    pub span: Span,
+}
+
+impl Token {
+    /// the token is only layout: a comment, spaces or a line break
+    pub fn is_trivia(&self) -> bool {
+        matches!(
+            self.kind,
+            TokenKind::Comment | TokenKind::Whitespace | TokenKind::Newline
+        )
+    }
+}
+
+/// A [`Token`] borrowing from the source, or from a token
+pub(crate) struct TokenRef<'a> {
+    kind: KindRef<'a>,
+    text: &'a str,
+    span: Span,
+}
+
+/// Every token of a recipe, in the order of the source
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SyntaxTree {
+    pub tokens: Vec<Token>,
+}
+
+/// the comments, spaces and line breaks of `gap`, which starts at `offset`
+fn trivia<'a>(locator: &Locator, gap: &'a str, offset: usize, tokens: &mut Vec<TokenRef<'a>>) {
+    let mut rest = gap;
+    while !rest.is_empty() {
+        let start = offset + gap.len() - rest.len();
+        let (kind, len) = if let Ok((after, _)) = comment(rest) {
+            (KindRef::Comment, rest.len() - after.len())
+        } else if rest.starts_with("\r\n") {
+            (KindRef::Newline, 2)
+        } else if rest.starts_with(['\n', '\r']) {
+            (KindRef::Newline, 1)
+        } else {
+            // up to the next line break or comment, at least one character
+            let len = rest
+                .char_indices()
+                .skip(1)
+                .find(|&(i, c)| "\n\r".contains(c) || comment(&rest[i..]).is_ok())
+                .map_or(rest.len(), |(i, _)| i);
+            (KindRef::Whitespace, len)
+        };
+        tokens.push(TokenRef {
+            kind,
+            text: &rest[..len],
+            span: locator.span(start, start + len),
+        });
+        rest = &rest[len..];
+    }
+}
+
+/// The tokens of `input`, borrowing from it, with the text skipped after
+/// each error as an error token, and the errors
+pub(crate) fn tokens(input: &str) -> (Vec<TokenRef<'_>>, Vec<ParseError>) {
+    let source = input.trim_end_matches([' ', '\t', '\r', '\n']);
+    let locator = Locator::new(input);
+    let mut elements = Vec::new();
+    let (lines, errors) = lines(source);
+    for line in lines {
+        match line {
+            Line::FrontMatter(values, span) => {
+                elements.push((KindRef::FrontMatter(Cow::Owned(values)), span))
+            }
+            Line::Metadata(m, span) => elements.push((KindRef::Metadata(Cow::Owned(m)), span)),
+            Line::Section(name, span) => elements.push((KindRef::Section(name), span)),
+            Line::Step(parts) => elements.extend(parts.into_iter().map(|p| {
+                let span = p.span();
+                (KindRef::Part(p), span)
+            })),
+            Line::Error(span) => elements.push((KindRef::Error, span)),
+        }
+    }
+
+    let mut tokens = Vec::new();
+    let mut end = 0;
+    for (kind, span) in elements {
+        trivia(&locator, &input[end..span.start], end, &mut tokens);
+        tokens.push(TokenRef {
+            kind,
+            text: &input[span.start..span.end],
+            span,
+        });
+        end = span.end;
+    }
+    trivia(&locator, &input[end..], end, &mut tokens);
+    (tokens, errors)
+}
+
+impl SyntaxTree {
+    pub fn parse(input: &str) -> Result<Self, ParseError> {
+        let (tree, errors) = Self::parse_recovering(input);
+        match errors.into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(tree),
+        }
+    }
+
+    /// The tree of `input`, with the text skipped after each error as an
+    /// [`TokenKind::Error`] token, and the errors
+    pub fn parse_recovering(input: &str) -> (Self, Vec<ParseError>) {
+        let (tokens, errors) = tokens(input);
+        let tokens = tokens
+            .into_iter()
+            .map(|t| Token {
+                kind: t.kind.into_owned(),
+                text: t.text.to_string(),
+                span: t.span,
+            })
+            .collect();
+        (SyntaxTree { tokens }, errors)
+    }
+
+    /// The recipe of the tree
+    pub fn recipe(&self) -> Result<Recipe, ParseError> {
+        let (recipe, errors) = self.recipe_recovering();
+        match errors.into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(recipe),
+        }
+    }
+
+    /// The recipe of the tree, without the error tokens, and its errors. A
+    /// reference to an ingredient not defined yet is reported and read as
+    /// its definition.
+    pub fn recipe_recovering(&self) -> (Recipe, Vec<ParseError>) {
+        let mut builder = RecipeBuilder::new();
+        for token in &self.tokens {
+            builder.push(TokenRef {
+                kind: token.kind.as_borrowed(),
+                text: &token.text,
+                span: token.span,
+            });
+        }
+        let (recipe, errors) = builder.finish(|| self.to_string());
+        (recipe.into_owned(), errors)
+    }
+}
+
+impl fmt::Display for SyntaxTree {
+    /// the source of the tree
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.tokens.iter().try_for_each(|t| f.write_str(&t.text))
+    }
+}
+
+/// Gathers tokens into a recipe
+#[derive(Default)]
+pub(crate) struct RecipeBuilder<'a> {
+    recipe: RecipeRef<'a>,
+    front_matter: IndexMap<String, MetadataValue>,
+    /// names of the ingredients that can be referenced
+    defined: HashSet<&'a str>,
+    /// parts of the line being read
+    step: StepRef<'a>,
+    /// spaces since the last part, comments excluded
+    spaces: String,
+    /// whether the current line has only spaces so far
+    blank: bool,
+    /// whether a blank line follows the last step
+    blank_line: bool,
+    /// offsets and messages of the references before their definition
+    errors: Vec<(usize, String)>,
+}
+
+impl<'a> RecipeBuilder<'a> {
+    pub(crate) fn new() -> Self {
+        RecipeBuilder {
+            blank: true,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn push(&mut self, token: TokenRef<'a>) {
+        let (whitespace, newline, comment) = match token.kind {
+            KindRef::Whitespace => (true, false, false),
+            KindRef::Newline => (false, true, false),
+            KindRef::Comment => (false, false, true),
+            _ => (false, false, false),
+        };
+        match token.kind {
+            KindRef::FrontMatter(values) => {
+                self.recipe.metadata.clone_from(&values);
+                self.front_matter = values.into_owned();
+            }
+            KindRef::Metadata(m) => self.metadata(&m, token.span),
+            KindRef::Section(name) => self.recipe.sections.push(SectionRef {
+                name,
+                span: token.span,
+                ..Default::default()
+            }),
+            KindRef::Part(part @ PartRef::Note(_)) => {
+                // a note is a step by itself
+                self.end_step();
+                self.step.parts.push(part);
+                self.end_step();
+            }
+            KindRef::Part(part) => match (self.step.parts.last_mut(), part) {
+                // texts only separated by comments are a single text
+                (Some(PartRef::Text(previous)), PartRef::Text(next)) => {
+                    let value = format!("{}{}{}", previous.value, self.spaces, next.value);
+                    previous.value = Cow::Owned(value);
+                    previous.span.end = next.span.end;
+                }
+                (_, part) => self.step.parts.push(part),
+            },
+            KindRef::Comment | KindRef::Error => {}
+            KindRef::Whitespace => self.spaces.push_str(token.text),
+            KindRef::Newline => {
+                self.end_step();
+                self.blank_line |= self.blank;
+            }
+        }
+        if !whitespace {
+            self.blank = newline;
+        }
+        if !whitespace && !comment {
+            self.spaces.clear();
+        }
+    }
+
+    /// the recipe, and the errors in `source`, called only when there are
+    /// some
+    pub(crate) fn finish(
+        mut self,
+        source: impl FnOnce() -> String,
+    ) -> (RecipeRef<'a>, Vec<ParseError>) {
+        self.end_step();
+        if self.errors.is_empty() {
+            return (self.recipe, Vec::new());
+        }
+        let source = source();
+        let locator = Locator::new(&source);
+        let errors = self
+            .errors
+            .into_iter()
+            .map(|(offset, message)| {
+                ParseError::at(&locator, offset, Expected::Definition, message)
+            })
+            .collect();
+        (self.recipe, errors)
+    }
+
+    /// `>>` metadata do not override the front matter
+    fn metadata(&mut self, m: &Metadata, span: Span) {
+        match self.front_matter.get(&m.key) {
+            Some(value) if *value != *m.value => self.recipe.warnings.push(Warning {
+                span,
+                message: format!(
+                    "`{}` is already `{value}` in the front matter, `{}` is ignored",
+                    m.key, m.value
+                ),
+            }),
+            Some(_) => {}
+            None => {
+                self.recipe
+                    .metadata
+                    .insert(m.key.clone(), m.value.as_str().into());
+            }
+        }
+    }
+
+    /// add the parts read so far as a step, in the paragraph of the previous
+    /// step unless a blank line separates them
+    fn end_step(&mut self) {
+        let mut step = std::mem::take(&mut self.step);
+        let (Some(first), Some(last)) = (step.parts.first(), step.parts.last()) else {
+            return;
+        };
+        step.span = Span {
+            end: last.span().end,
+            ..first.span()
+        };
+        for part in &mut step.parts {
+            match part {
+                PartRef::Ingredient(i) if i.reference && !self.defined.contains(i.name) => {
+                    self.errors.push((
+                        i.span.start,
+                        format!("`{}` is referenced before its definition", i.name),
+                    ));
+                    i.reference = false;
+                    self.defined.insert(i.name);
+                }
+                PartRef::Ingredient(i) => {
+                    self.defined.insert(i.name);
+                }
+                _ => {}
+            }
+        }
+        let recipe = &mut self.recipe;
+        if recipe.sections.is_empty() {
+            recipe.sections.push(SectionRef::default());
+        }
+        let index = recipe.steps.len();
+        let section = recipe.sections.last_mut().unwrap();
+        match section.paragraphs.last_mut() {
+            Some(paragraph) if !self.blank_line => paragraph.end += 1,
+            _ => section.paragraphs.push(index..index + 1),
+        }
+        self.blank_line = false;
+        recipe.steps.push(step);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse, Text};
+    use pretty_assertions::assert_eq;
+
+    fn kinds(tree: &SyntaxTree) -> Vec<(&str, &str)> {
+        tree.tokens
+            .iter()
+            .map(|t| {
+                let kind = match &t.kind {
+                    TokenKind::FrontMatter(_) => "front matter",
+                    TokenKind::Metadata(_) => "metadata",
+                    TokenKind::Section(_) => "section",
+                    TokenKind::Part(Part::Ingredient(_)) => "ingredient",
+                    TokenKind::Part(Part::Cookware(_)) => "cookware",
+                    TokenKind::Part(Part::Timer(_)) => "timer",
+                    TokenKind::Part(Part::Text(_)) => "text",
+                    TokenKind::Part(Part::Note(_)) => "note",
+                    TokenKind::Comment => "comment",
+                    TokenKind::Whitespace => "whitespace",
+                    TokenKind::Newline => "newline",
+                    TokenKind::Error => "error",
+                };
+                (kind, t.text.as_str())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_tokens() {
+        let tree = SyntaxTree::parse(
+            ">> servings: 2\r\n\n-- two\nMix  @flour{250%g} [- sifted -] in a #bowl.  \n",
+        )
+        .unwrap();
+        assert_eq!(
+            kinds(&tree),
+            vec![
+                ("metadata", ">> servings: 2"),
+                ("newline", "\r\n"),
+                ("newline", "\n"),
+                ("comment", "-- two"),
+                ("newline", "\n"),
+                ("text", "Mix"),
+                ("whitespace", "  "),
+                ("ingredient", "@flour{250%g}"),
+                ("whitespace", " "),
+                ("comment", "[- sifted -]"),
+                ("whitespace", " "),
+                ("text", "in a"),
+                ("whitespace", " "),
+                ("cookware", "#bowl"),
+                ("text", "."),
+                ("whitespace", "  "),
+                ("newline", "\n"),
+            ]
+        );
+        let flour = &tree.tokens[7];
+        assert_eq!((flour.span.line, flour.span.column), (4, 6));
+    }
+
+    #[test]
+    fn test_lossless() {
+        let sources = [
+            "",
+            "  \n\n",
+            "---\ntitle: Soup\ntags: [a, b]\n---\n\n== Prep ==  \n> Be quick\n\
+            Chop @?onion{1}(diced) -- finely\n\n~{5%min} then [- a\nblock -] @&onion{}\n",
+            "Add @salt\r\nthen @pepper{} -- end",
+            "-- only a comment\n\t\n",
+        ];
+        for source in sources {
+            let tree = SyntaxTree::parse(source).unwrap();
+            assert_eq!(tree.to_string(), source);
+            let mut end = 0;
+            for token in &tree.tokens {
+                assert_eq!(token.span.start, end);
+                assert_eq!(&source[token.span.start..token.span.end], token.text);
+                end = token.span.end;
+            }
+        }
+    }
+
+    #[test]
+    fn test_recovering() {
+        let source = "Add @{1} now\r\n  Stir #pot{ -- stirring\nBake.\n";
+        let (tree, errors) = SyntaxTree::parse_recovering(source);
+        assert_eq!(tree.to_string(), source);
+        assert_eq!(
+            kinds(&tree),
+            vec![
+                ("error", "Add @{1} now"),
+                ("newline", "\r\n"),
+                ("whitespace", "  "),
+                ("error", "Stir #pot{ -- stirring"),
+                ("newline", "\n"),
+                ("text", "Bake."),
+                ("newline", "\n"),
+            ]
+        );
+        let lines: Vec<_> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![1, 2]);
+        assert_eq!(SyntaxTree::parse(source), Err(errors[0].clone()));
+        assert_eq!(tree.recipe().unwrap().steps.len(), 1);
+    }
+
+    #[test]
+    fn test_recipe() {
+        let tree = SyntaxTree::parse(
+            ">> servings: 2\nStir @flour gently [- slowly -] then  [- rest -] with @water\n\n-- comment\nBake.",
+        )
+        .unwrap();
+        let recipe = tree.recipe().unwrap();
+        let Part::Text(text) = &recipe.steps[0].parts[2] else {
+            panic!("expected a text");
+        };
+        assert_eq!(text.value, "gently  then   with");
+        assert_eq!(
+            recipe.sections[0].paragraphs,
+            vec![0..1, 1..2],
+            "the blank line starts a paragraph"
+        );
+        assert_eq!(
+            recipe.steps[1].parts,
+            vec![Part::Text(Text {
+                value: "Bake.".to_string(),
+                span: tree.tokens.last().unwrap().span,
+            })]
+        );
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut tree = SyntaxTree::parse("Add @salt{1%pinch} -- to taste\n").unwrap();
+        for token in &mut tree.tokens {
+            if let TokenKind::Part(Part::Ingredient(i)) = &token.kind {
+                token.text = format!("@{}{{2%pinch}}", i.name);
+            }
+        }
+        let source = tree.to_string();
+        assert_eq!(source, "Add @salt{2%pinch} -- to taste\n");
+        let recipe = parse(source).unwrap();
+        assert_eq!(recipe.ingredients()[0].units, "pinch");
+    }
+}
@@ -0,0 +1,325 @@
+//! Canonical layout of recipes.
+//!
+//! [`format`] rewrites the elements of a recipe the way [`to_cooklang`]
+//! writes them: amounts without spaces (`@chilli{3%items}`), no braces on a
+//! single word without amount, `== name ==` headers and `> ` notes. The
+//! values of consecutive `>>` lines are aligned, spaces between elements are
+//! reduced to one, trailing spaces and repeated blank lines are removed and
+//! lines end with `\n`. Comments, the front matter and texts are kept as
+//! written, so the formatted recipe parses to the same recipe. The blank
+//! line before a leading `---` comment, and the indentation of a text that
+//! would otherwise read as metadata, a note or a header, are kept.
+//!
+//! [`to_cooklang`]: crate::parser::to_cooklang
+
+use crate::cst::{SyntaxTree, Token, TokenKind};
+use crate::parser::{write_part, ParseError, Part};
+
+/// whether the first part of `tokens` at `indices`, on the same line, is a
+/// text, and whether comments come before it
+fn nearest_text(tokens: &[Token], indices: impl Iterator<Item = usize>) -> Option<bool> {
+    let mut comment = false;
+    for i in indices {
+        match &tokens[i].kind {
+            TokenKind::Whitespace => {}
+            TokenKind::Comment => comment = true,
+            TokenKind::Part(Part::Text(_)) => return Some(comment),
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// whether the spaces of token `i` are inside a text, between comments
+/// that the parser merges into a single text, where they are significant
+fn inside_text(tokens: &[Token], i: usize) -> bool {
+    let before = nearest_text(tokens, (0..i).rev());
+    let after = nearest_text(tokens, i + 1..tokens.len());
+    matches!((before, after), (Some(a), Some(b)) if a || b)
+}
+
+/// `>> key: value` lines, with their values aligned on the longest key of
+/// the lines that follow each other
+fn metadata_widths(tokens: &[Token]) -> Vec<usize> {
+    let mut widths = vec![0; tokens.len()];
+    // indices of the metadata of the current group
+    let mut group: Vec<usize> = Vec::new();
+    let mut end_group = |group: &mut Vec<usize>| {
+        let width = group
+            .iter()
+            .filter_map(|&i| match &tokens[i].kind {
+                TokenKind::Metadata(m) => Some(m.key.chars().count()),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        for i in group.drain(..) {
+            widths[i] = width;
+        }
+    };
+    // line breaks since the last metadata, the formatter writes a lone `\r` as one
+    let mut newlines = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::Newline => newlines += 1,
+            TokenKind::Metadata(_) => {
+                if newlines != 1 {
+                    end_group(&mut group);
+                }
+                group.push(i);
+                newlines = 0;
+            }
+            _ => {}
+        }
+    }
+    end_group(&mut group);
+    widths
+}
+
+/// Format the recipe `source`, or return why it does not parse
+pub fn format(source: &str) -> Result<String, ParseError> {
+    let tree = SyntaxTree::parse(source)?;
+    let tokens = &tree.tokens;
+    let widths = metadata_widths(tokens);
+    let mut lines = vec![String::new()];
+    for (i, token) in tokens.iter().enumerate() {
+        let line = lines.last_mut().unwrap();
+        match &token.kind {
+            TokenKind::FrontMatter(_) => line.push_str(&token.text.replace("\r\n", "\n")),
+            // the block comments inside a value, a note or a header are kept
+            // where they are
+            TokenKind::Metadata(_) | TokenKind::Part(Part::Note(_)) | TokenKind::Section(_)
+                if token.text.contains("[-") =>
+            {
+                line.push_str(&token.text)
+            }
+            TokenKind::Metadata(m) if m.value.is_empty() => {
+                line.push_str(&format!(">> {}:", m.key))
+            }
+            TokenKind::Metadata(m) => {
+                let padding = widths[i] - m.key.chars().count();
+                line.push_str(&format!(">> {}:{:padding$} {}", m.key, "", m.value));
+            }
+            // spaces around an empty note are written by the whitespace tokens
+            TokenKind::Part(Part::Note(n)) if n.value.is_empty() => line.push('>'),
+            TokenKind::Section(name) if name.is_empty() => line.push_str("=="),
+            TokenKind::Section(name) => line.push_str(&format!("== {name} ==")),
+            TokenKind::Part(Part::Text(_)) => line.push_str(&token.text),
+            TokenKind::Part(part) => {
+                let start = line.len();
+                write_part(line, part);
+                // without its braces, the name would run into the next text
+                // or comment
+                let glued = match tokens.get(i + 1).map(|t| &t.kind) {
+                    Some(TokenKind::Part(Part::Text(t))) => !t.value.starts_with(['.', ',', ';']),
+                    Some(TokenKind::Comment) => true,
+                    _ => false,
+                };
+                // names never hold a `{`, so there is one when braces were written
+                let item = !matches!(part, Part::Note(_));
+                if item && glued && token.text.ends_with('}') && !line[start..].contains('{') {
+                    line.push_str("{}");
+                }
+            }
+            TokenKind::Comment | TokenKind::Error => {
+                // a value written trimmed would run into the comment
+                let value = i.checked_sub(1).map(|i| &tokens[i].kind);
+                if matches!(
+                    value,
+                    Some(TokenKind::Metadata(_) | TokenKind::Part(Part::Note(_)))
+                ) && !line.ends_with([' ', '>', ':'])
+                {
+                    line.push(' ');
+                }
+                line.push_str(token.text.trim_end_matches([' ', '\t', '\r']))
+            }
+            // a text starting like metadata, a note or a header is one
+            // because of its indentation
+            TokenKind::Whitespace if line.is_empty() => {
+                if matches!(
+                    tokens.get(i + 1),
+                    Some(Token { kind: TokenKind::Part(Part::Text(_)), text, .. })
+                        if text.starts_with(['>', '='])
+                ) {
+                    line.push_str(&token.text);
+                }
+            }
+            TokenKind::Whitespace if inside_text(tokens, i) => line.push_str(&token.text),
+            TokenKind::Whitespace => line.push(' '),
+            TokenKind::Newline => lines.push(String::new()),
+        }
+    }
+
+    let mut out = String::new();
+    // a `---` comment on the first line would start a front matter
+    let front_matter =
+        matches!(tokens.first(), Some(t) if matches!(t.kind, TokenKind::FrontMatter(_)));
+    if !front_matter
+        && lines
+            .iter()
+            .find(|l| !l.trim().is_empty())
+            .is_some_and(|l| l.starts_with("---"))
+    {
+        out.push('\n');
+    }
+    let mut blank = false;
+    for line in &lines {
+        let line = line.trim_end_matches([' ', '\t']);
+        if line.is_empty() {
+            blank = !out.trim().is_empty();
+            continue;
+        }
+        if blank {
+            out.push('\n');
+            blank = false;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse, Recipe, Span};
+    use pretty_assertions::assert_eq;
+    use proptest::prelude::*;
+
+    /// the recipe of `source` without its spans, which formatting changes
+    fn recipe(source: &str) -> Result<Recipe, ParseError> {
+        let mut recipe = parse(source.to_string())?;
+        for step in recipe.steps.iter_mut() {
+            step.span = Span::default();
+            for part in step.parts.iter_mut() {
+                match part {
+                    Part::Cookware(c) => c.span = Span::default(),
+                    Part::Timer(t) => t.span = Span::default(),
+                    Part::Ingredient(i) => i.span = Span::default(),
+                    Part::Text(t) => t.span = Span::default(),
+                    Part::Note(n) => n.span = Span::default(),
+                }
+            }
+        }
+        for section in recipe.sections.iter_mut() {
+            section.span = Span::default();
+        }
+        for warning in recipe.warnings.iter_mut() {
+            warning.span = Span::default();
+        }
+        Ok(recipe)
+    }
+
+    #[test]
+    fn test_amounts() {
+        assert_eq!(
+            format("Add @chilli{ 3 % items } and @salt{}, @sea salt{ } and ~{ 1 / 2 %hour}")
+                .unwrap(),
+            "Add @chilli{3%items} and @salt, @sea salt{} and ~{1/2%hour}\n"
+        );
+        assert_eq!(
+            format("Add @?-garlic{=2 %cloves}(crushed) then #pot{} and @&garlic{}").unwrap(),
+            "Add @?-garlic{=2%cloves}(crushed) then #pot and @&garlic\n"
+        );
+        // the braces end the name
+        assert_eq!(format("Add @salt{}y").unwrap(), "Add @salt{}y\n");
+    }
+
+    #[test]
+    fn test_layout() {
+        let source = "\n\n>>servings:2\r\n>> total time :  1 hour\n\n\n\
+            == Dough==  \n\tMix   @flour{250%g}  in a #bowl .  \n\n\n=Sauce\n>Quick\n\n";
+        assert_eq!(
+            format(source).unwrap(),
+            ">> servings:   2\n>> total time: 1 hour\n\n\
+            == Dough ==\nMix @flour{250%g} in a #bowl .\n\n== Sauce ==\n> Quick\n"
+        );
+        assert_eq!(format("").unwrap(), "");
+        assert_eq!(format(" \n\n").unwrap(), "");
+
+        // kept where removing them would change the recipe
+        assert_eq!(
+            format("\n\n---\nMix @flour{1%g}\n").unwrap(),
+            "\n---\nMix @flour{1%g}\n"
+        );
+        assert_eq!(
+            format("  >> servings: 4\n\tMix").unwrap(),
+            "  >> servings: 4\nMix\n"
+        );
+        assert_eq!(format(">>.:----").unwrap(), ">> .:----\n");
+    }
+
+    #[test]
+    fn test_comments() {
+        let source = "-- intro\nStir  gently [- slowly -]  then  rest @salt -- to taste\n\
+            [- several\nlines -]  Bake.";
+        let formatted = format(source).unwrap();
+        assert_eq!(
+            formatted,
+            "-- intro\nStir  gently [- slowly -]  then  rest @salt -- to taste\n\
+            [- several\nlines -] Bake.\n"
+        );
+        let text = |s: &str| match &crate::parser::parse(s.to_string()).unwrap().steps[0].parts[0] {
+            Part::Text(t) => t.value.clone(),
+            _ => panic!("expected a text"),
+        };
+        assert_eq!(text(&formatted), text(source));
+        assert_eq!(
+            format(">>source:grandma [- old -] book  \n").unwrap(),
+            ">>source:grandma [- old -] book\n"
+        );
+        assert_eq!(
+            format(">Serve hot [- really -]  with bread").unwrap(),
+            ">Serve hot [- really -]  with bread\n"
+        );
+        assert_eq!(
+            format("=Dough [- x -] part\n").unwrap(),
+            "=Dough [- x -] part\n"
+        );
+    }
+
+    #[test]
+    fn test_front_matter() {
+        let source = "---\r\ntitle: Soup   # yaml comment\r\n---\r\n>> servings: 2\r\nBoil.";
+        assert_eq!(
+            format(source).unwrap(),
+            "---\ntitle: Soup   # yaml comment\n---\n>> servings: 2\nBoil.\n"
+        );
+    }
+
+    #[test]
+    fn test_idempotent() {
+        let sources = [
+            ">> a: 1\n>> long key: 2\n\n>> b: 3\nMix @a{ 1 }  and #b{}.\n\n\n\nThen ~t{2%min}",
+            "Stir [- a -]  gently [- b -] then -- end\n= S =\n> note  ",
+            ">>.:----",
+            ">-\u{a0}--\n>> a: 1\r>> bb: 2",
+            "#-{}--\n@}{}>>}\n~{==}",
+        ];
+        for source in sources {
+            let formatted = format(source).unwrap();
+            assert_eq!(format(&formatted).unwrap(), formatted);
+        }
+    }
+
+    /// recipes made of pieces of the syntax, most of them valid
+    fn source() -> impl Strategy<Value = String> {
+        let piece = prop::sample::select(vec![
+            "\n", "\r\n", " ", "  ", "\t", ">>", ">", ":", "=", "==", "-", "--", "---", "[-", "-]",
+            "@", "#", "~", "{", "}", "%", "(", ")", "?", "&", "=", "./", "flour", "salt", "a b",
+            "2", "1/2", ".", ",", "é", "yaml: 1", "\r", "|", "\u{a0}",
+        ]);
+        prop::collection::vec(piece, 0..24).prop_map(|pieces| pieces.concat())
+    }
+
+    proptest! {
+        #[test]
+        fn test_format_properties(source in source()) {
+            if let Ok(expected) = recipe(&source) {
+                let formatted = format(&source).unwrap();
+                prop_assert_eq!(recipe(&formatted), Ok(expected), "formatted {:?}", formatted);
+                prop_assert_eq!(format(&formatted), Ok(formatted.clone()));
+            }
+        }
+    }
+}
@@ -1 +1,10 @@
+pub mod borrowed;
+pub mod cst;
+pub mod format;
+pub mod lint;
+pub mod metadata;
 pub mod parser;
+pub mod resolve;
+pub mod scale;
+pub mod shopping;
+pub mod units;
@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use indexmap::IndexMap;
+
+use crate::cst::{SyntaxTree, TokenKind};
+use crate::parser::{ParseError, Part, Quantity, Recipe, Span};
+use crate::units::UnitsTable;
+
+/// Metadata keys with a common meaning, misspellings of which are reported
+const KNOWN_KEYS: &[&str] = &[
+    "author",
+    "cook time",
+    "course",
+    "cuisine",
+    "description",
+    "diet",
+    "difficulty",
+    "image",
+    "introduction",
+    "locale",
+    "prep time",
+    "servings",
+    "serves",
+    "source",
+    "tags",
+    "time",
+    "time required",
+    "title",
+];
+
+/// How much a rule matters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// the rule is not checked
+    Off,
+    Warning,
+    Error,
+}
+
+impl FromStr for Severity {
+    type Err = LintError;
+
+    fn from_str(s: &str) -> Result<Self, LintError> {
+        match s {
+            "off" => Ok(Severity::Off),
+            "warning" => Ok(Severity::Warning),
+            "error" => Ok(Severity::Error),
+            _ => Err(LintError::Config(format!("unknown severity `{s}`"))),
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Off => "off",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// A check of the recipes, reporting the spans of its findings
+pub struct Rule {
+    /// name used in the configuration and the reports
+    pub name: &'static str,
+    pub description: &'static str,
+    /// severity when the configuration does not set one
+    pub severity: Severity,
+    check: fn(&Recipe, &SyntaxTree) -> Vec<(Span, String)>,
+}
+
+/// Every rule, by name
+pub const RULES: &[Rule] = &[
+    Rule {
+        name: "missing-quantity",
+        description: "ingredients without a quantity",
+        severity: Severity::Warning,
+        check: missing_quantity,
+    },
+    Rule {
+        name: "mixed-units",
+        description: "the same unit written in different ways, like `grams` and `g`",
+        severity: Severity::Warning,
+        check: mixed_units,
+    },
+    Rule {
+        name: "misspelled-metadata",
+        description: "metadata keys close to a common key, like `serving` for `servings`",
+        severity: Severity::Warning,
+        check: misspelled_metadata,
+    },
+    Rule {
+        name: "timer-without-units",
+        description: "timers with a quantity but no units",
+        severity: Severity::Warning,
+        check: timer_without_units,
+    },
+    Rule {
+        name: "unused-cookware",
+        description: "cookware used in a single step of a recipe with several steps",
+        severity: Severity::Warning,
+        check: unused_cookware,
+    },
+];
+
+/// Error returned when a lint configuration is not valid
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LintError {
+    /// the TOML configuration is not valid
+    Config(String),
+    /// no rule has this name
+    UnknownRule(String),
+}
+
+impl fmt::Display for LintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintError::Config(e) => write!(f, "invalid lint configuration: {e}"),
+            LintError::UnknownRule(r) => write!(f, "unknown lint rule `{r}`"),
+        }
+    }
+}
+
+impl std::error::Error for LintError {}
+
+/// Severities of the rules, the defaults of the rules unless set.
+///
+/// In TOML, severities are set in a `rules` table:
+///
+/// ```toml
+/// [rules]
+/// missing-quantity = "off"
+/// mixed-units = "error"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LintConfig {
+    severities: HashMap<String, Severity>,
+}
+
+impl LintConfig {
+    pub fn from_toml(toml: &str) -> Result<Self, LintError> {
+        let file: toml::Table = toml
+            .parse()
+            .map_err(|e: toml::de::Error| LintError::Config(e.to_string()))?;
+        let mut config = LintConfig::default();
+        let Some(rules) = file.get("rules") else {
+            return Ok(config);
+        };
+        let rules = rules
+            .as_table()
+            .ok_or_else(|| LintError::Config("`rules` is not a table".to_string()))?;
+        for (rule, severity) in rules {
+            let severity = severity.as_str().ok_or_else(|| {
+                LintError::Config(format!("the severity of `{rule}` is not a string"))
+            })?;
+            config.set(rule, severity.parse()?)?;
+        }
+        Ok(config)
+    }
+
+    pub fn set(&mut self, rule: &str, severity: Severity) -> Result<(), LintError> {
+        if !RULES.iter().any(|r| r.name == rule) {
+            return Err(LintError::UnknownRule(rule.to_string()));
+        }
+        self.severities.insert(rule.to_string(), severity);
+        Ok(())
+    }
+
+    pub fn severity(&self, rule: &Rule) -> Severity {
+        self.severities
+            .get(rule.name)
+            .copied()
+            .unwrap_or(rule.severity)
+    }
+}
+
+/// A finding of a rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub span: Span,
+    /// short human readable description
+    pub message: String,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}: {} [{}]",
+            self.span.line, self.span.column, self.severity, self.message, self.rule
+        )
+    }
+}
+
+/// Check the recipe `source` with the rules that are not off, in the order
+/// of the source
+pub fn lint(source: &str, config: &LintConfig) -> Result<Vec<Lint>, ParseError> {
+    let tree = SyntaxTree::parse(source)?;
+    let recipe = tree.recipe()?;
+    let mut lints = Vec::new();
+    for rule in RULES {
+        let severity = config.severity(rule);
+        if severity == Severity::Off {
+            continue;
+        }
+        lints.extend(
+            (rule.check)(&recipe, &tree)
+                .into_iter()
+                .map(|(span, message)| Lint {
+                    rule: rule.name,
+                    severity,
+                    span,
+                    message,
+                }),
+        );
+    }
+    lints.sort_by_key(|l| l.span.start);
+    Ok(lints)
+}
+
+fn parts(recipe: &Recipe) -> impl Iterator<Item = &Part> {
+    recipe.steps.iter().flat_map(|s| &s.parts)
+}
+
+fn missing_quantity(recipe: &Recipe, _: &SyntaxTree) -> Vec<(Span, String)> {
+    parts(recipe)
+        .filter_map(|part| match part {
+            Part::Ingredient(i)
+                if i.quantity == Quantity::Empty && !i.reference && !i.recipe_link =>
+            {
+                Some((i.span, format!("`{}` has no quantity", i.name)))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn mixed_units(recipe: &Recipe, _: &SyntaxTree) -> Vec<(Span, String)> {
+    let table = UnitsTable::bundled();
+    // first spelling of every unit
+    let mut spellings: IndexMap<&str, &str> = IndexMap::new();
+    let mut lints = Vec::new();
+    for part in parts(recipe) {
+        let (units, span) = match part {
+            Part::Ingredient(i) => (i.units.as_str(), i.span),
+            Part::Timer(t) => (t.units.as_str(), t.span),
+            _ => continue,
+        };
+        let Some(unit) = table.get(units) else {
+            continue;
+        };
+        let first = *spellings.entry(unit.name.as_str()).or_insert(units);
+        if first != units {
+            lints.push((
+                span,
+                format!("`{units}` is written `{first}` elsewhere in the recipe"),
+            ));
+        }
+    }
+    lints
+}
+
+/// number of single character edits from `a` to `b`
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// the common key `key` is likely a misspelling of
+fn misspelling_of(key: &str) -> Option<&'static str> {
+    let key = key.to_lowercase();
+    if KNOWN_KEYS.contains(&key.as_str()) {
+        return None;
+    }
+    let allowed = if key.chars().count() <= 4 { 1 } else { 2 };
+    KNOWN_KEYS
+        .iter()
+        .map(|known| (distance(&key, known), *known))
+        .filter(|&(d, _)| d <= allowed)
+        .min()
+        .map(|(_, known)| known)
+}
+
+fn misspelled_metadata(_: &Recipe, tree: &SyntaxTree) -> Vec<(Span, String)> {
+    let mut keys = Vec::new();
+    for token in &tree.tokens {
+        match &token.kind {
+            TokenKind::Metadata(m) => keys.push((m.key.clone(), token.span)),
+            TokenKind::FrontMatter(values) => {
+                for key in values.keys() {
+                    // the line of the key, or the whole front matter
+                    let line = token.text.split('\n').position(|line| {
+                        line.strip_prefix(key.as_str())
+                            .is_some_and(|rest| rest.trim_start().starts_with(':'))
+                    });
+                    let span = match line {
+                        Some(line) => {
+                            let start = token.span.start
+                                + token
+                                    .text
+                                    .split('\n')
+                                    .take(line)
+                                    .map(|l| l.len() + 1)
+                                    .sum::<usize>();
+                            Span {
+                                start,
+                                end: start + key.len(),
+                                line: token.span.line + line,
+                                column: 1,
+                            }
+                        }
+                        None => token.span,
+                    };
+                    keys.push((key.clone(), span));
+                }
+            }
+            _ => {}
+        }
+    }
+    keys.into_iter()
+        .filter_map(|(key, span)| {
+            let known = misspelling_of(&key)?;
+            Some((
+                span,
+                format!("unknown metadata key `{key}`, did you mean `{known}`?"),
+            ))
+        })
+        .collect()
+}
+
+fn timer_without_units(recipe: &Recipe, _: &SyntaxTree) -> Vec<(Span, String)> {
+    parts(recipe)
+        .filter_map(|part| match part {
+            Part::Timer(t) if t.quantity != Quantity::Empty && t.units.is_empty() => {
+                let message = match t.name.as_str() {
+                    "" => format!("timer of {} has no units", t.quantity),
+                    name => format!("timer `{name}` has no units"),
+                };
+                Some((t.span, message))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn unused_cookware(recipe: &Recipe, _: &SyntaxTree) -> Vec<(Span, String)> {
+    if recipe.steps.iter().filter(|s| !s.is_note()).count() < 2 {
+        return Vec::new();
+    }
+    // steps using each cookware, and where it is first used
+    let mut uses: IndexMap<&str, (usize, Span)> = IndexMap::new();
+    for step in &recipe.steps {
+        let mut names: Vec<_> = step
+            .parts
+            .iter()
+            .filter_map(|part| match part {
+                Part::Cookware(c) => Some((c.name.as_str(), c.span)),
+                _ => None,
+            })
+            .collect();
+        names.dedup_by_key(|(name, _)| *name);
+        for (name, span) in names {
+            uses.entry(name).or_insert((0, span)).0 += 1;
+        }
+    }
+    uses.into_iter()
+        .filter(|(_, (count, _))| *count == 1)
+        .map(|(name, (_, span))| (span, format!("`{name}` is not used in any other step")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn messages(source: &str, config: &LintConfig) -> Vec<String> {
+        lint(source, config)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_rules() {
+        let source = "---\ntitle: Soup\nserving: 2\n---\n>> tag: soup\n\
+            Put @water{1%l} and @salt in a #pot.\n\
+            Add @carrots{200%grams}, @onion{100%g} and @&salt{}.\n\
+            Boil for ~{10} in the #pot with a #lid, then ~rest{5%min}.";
+        assert_eq!(
+            messages(source, &LintConfig::default()),
+            vec![
+                "3:1: warning: unknown metadata key `serving`, did you mean `servings`? [misspelled-metadata]",
+                "5:1: warning: unknown metadata key `tag`, did you mean `tags`? [misspelled-metadata]",
+                "6:21: warning: `salt` has no quantity [missing-quantity]",
+                "7:26: warning: `g` is written `grams` elsewhere in the recipe [mixed-units]",
+                "8:10: warning: timer of 10 has no units [timer-without-units]",
+                "8:35: warning: `lid` is not used in any other step [unused-cookware]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config() {
+        let config =
+            LintConfig::from_toml("[rules]\nmissing-quantity = \"off\"\nmixed-units = \"error\"")
+                .unwrap();
+        assert_eq!(
+            messages("Add @salt, @flour{1%kg} and @flour{500%kilograms}", &config),
+            vec!["1:29: error: `kilograms` is written `kg` elsewhere in the recipe [mixed-units]"]
+        );
+        assert_eq!(
+            LintConfig::from_toml("[rules]\nmissing-quantities = \"off\""),
+            Err(LintError::UnknownRule("missing-quantities".to_string()))
+        );
+        assert_eq!(
+            LintConfig::from_toml("[rules]\nmissing-quantity = \"loud\""),
+            Err(LintError::Config("unknown severity `loud`".to_string()))
+        );
+        assert!(matches!(
+            LintConfig::from_toml("rules = 1"),
+            Err(LintError::Config(_))
+        ));
+        assert!(matches!(
+            LintConfig::from_toml("[rules"),
+            Err(LintError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_misspelling_of() {
+        assert_eq!(misspelling_of("Serving"), Some("servings"));
+        assert_eq!(misspelling_of("cusine"), Some("cuisine"));
+        assert_eq!(misspelling_of("preptime"), Some("prep time"));
+        assert_eq!(misspelling_of("servings"), None);
+        assert_eq!(misspelling_of("ratio"), None);
+        assert_eq!(misspelling_of("id"), None);
+    }
+}
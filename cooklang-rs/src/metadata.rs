@@ -0,0 +1,549 @@
+use std::fmt;
+use std::time::Duration;
+
+use indexmap::IndexMap;
+
+use crate::parser::Recipe;
+use crate::units::{PhysicalQuantity, UnitsTable};
+
+/// A metadata value.
+///
+/// `>> key: value` metadata are always strings, the YAML front matter can
+/// hold any of the other values.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
+pub enum MetadataValue {
+    #[default]
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    List(Vec<MetadataValue>),
+    Map(IndexMap<String, MetadataValue>),
+}
+
+impl MetadataValue {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            MetadataValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn from_yaml(value: serde_yaml::Value) -> Self {
+        match value {
+            serde_yaml::Value::Null => MetadataValue::Null,
+            serde_yaml::Value::Bool(b) => MetadataValue::Bool(b),
+            serde_yaml::Value::Number(n) => MetadataValue::Number(n.as_f64().unwrap_or_default()),
+            serde_yaml::Value::String(s) => MetadataValue::String(s),
+            serde_yaml::Value::Sequence(values) => {
+                MetadataValue::List(values.into_iter().map(Self::from_yaml).collect())
+            }
+            serde_yaml::Value::Mapping(mapping) => MetadataValue::Map(
+                mapping
+                    .into_iter()
+                    .map(|(k, v)| (yaml_key(k), Self::from_yaml(v)))
+                    .collect(),
+            ),
+            serde_yaml::Value::Tagged(tagged) => Self::from_yaml(tagged.value),
+        }
+    }
+
+    fn to_yaml(&self) -> serde_yaml::Value {
+        match self {
+            MetadataValue::Null => serde_yaml::Value::Null,
+            MetadataValue::Bool(b) => serde_yaml::Value::Bool(*b),
+            MetadataValue::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => {
+                serde_yaml::Value::Number((*n as i64).into())
+            }
+            MetadataValue::Number(n) => serde_yaml::Value::Number((*n).into()),
+            MetadataValue::String(s) => serde_yaml::Value::String(s.clone()),
+            MetadataValue::List(values) => {
+                serde_yaml::Value::Sequence(values.iter().map(Self::to_yaml).collect())
+            }
+            MetadataValue::Map(map) => serde_yaml::Value::Mapping(
+                map.iter()
+                    .map(|(k, v)| (serde_yaml::Value::String(k.clone()), v.to_yaml()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// keys of the front matter are strings, other scalars are written as they read
+fn yaml_key(key: serde_yaml::Value) -> String {
+    match key {
+        serde_yaml::Value::String(s) => s,
+        other => MetadataValue::from_yaml(other).to_string(),
+    }
+}
+
+/// Lists are separated by commas and maps written as `key: value`
+impl fmt::Display for MetadataValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetadataValue::Null => Ok(()),
+            MetadataValue::Bool(b) => write!(f, "{b}"),
+            MetadataValue::Number(n) => write!(f, "{n}"),
+            MetadataValue::String(s) => write!(f, "{s}"),
+            MetadataValue::List(values) => {
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                Ok(())
+            }
+            MetadataValue::Map(map) => {
+                for (i, (key, value)) in map.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{key}: {value}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl From<&str> for MetadataValue {
+    fn from(s: &str) -> Self {
+        MetadataValue::String(s.to_string())
+    }
+}
+
+impl From<String> for MetadataValue {
+    fn from(s: String) -> Self {
+        MetadataValue::String(s)
+    }
+}
+
+impl PartialEq<str> for MetadataValue {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == Some(other)
+    }
+}
+
+impl PartialEq<&str> for MetadataValue {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == Some(*other)
+    }
+}
+
+/// The `---` block at the start of a recipe
+pub(crate) struct FrontMatter {
+    pub(crate) values: IndexMap<String, MetadataValue>,
+    /// byte offset of the text following the block
+    pub(crate) end: usize,
+}
+
+/// Why the front matter could not be read, with the byte offset of the problem
+pub(crate) struct FrontMatterError {
+    pub(crate) offset: usize,
+    pub(crate) message: String,
+    /// byte offset after the front matter, or after its first line when it
+    /// is not closed, where parsing can resume
+    pub(crate) end: usize,
+}
+
+/// line ending of `line`, to skip it
+fn line_end(text: &str) -> Option<usize> {
+    text.strip_prefix("\r\n")
+        .or_else(|| text.strip_prefix('\n'))
+        .map(|rest| text.len() - rest.len())
+}
+
+/// Read the YAML front matter of `source`, if it starts with a `---` line
+/// closed by another one. Without the closing line, the first one is a
+/// comment.
+pub(crate) fn front_matter(source: &str) -> Result<Option<FrontMatter>, FrontMatterError> {
+    let Some(start) = source
+        .strip_prefix("---")
+        .and_then(line_end)
+        .map(|len| len + 3)
+    else {
+        return Ok(None);
+    };
+    // the closing `---` alone on its line, or at the end of the source
+    let mut offset = start;
+    let (yaml_end, end) = loop {
+        let line = &source[offset..];
+        let line_len = line.find('\n').map_or(line.len(), |i| i + 1);
+        if line[..line_len].trim_end() == "---" {
+            break (offset, offset + line_len);
+        }
+        if line_len == line.len() {
+            return Ok(None);
+        }
+        offset += line_len;
+    };
+
+    let yaml: serde_yaml::Value =
+        serde_yaml::from_str(&source[start..yaml_end]).map_err(|e| FrontMatterError {
+            offset: start + e.location().map_or(0, |l| l.index()),
+            message: format!("invalid front matter: {e}"),
+            end,
+        })?;
+    let values = match MetadataValue::from_yaml(yaml) {
+        MetadataValue::Map(values) => values,
+        MetadataValue::Null => IndexMap::new(),
+        _ => {
+            return Err(FrontMatterError {
+                offset: start,
+                message: "the front matter must be a map of keys to values".to_string(),
+                end,
+            })
+        }
+    };
+    Ok(Some(FrontMatter { values, end }))
+}
+
+/// The `---` block of `metadata`, for values that can not be written as `>>` metadata
+pub(crate) fn to_front_matter(metadata: &IndexMap<String, MetadataValue>) -> String {
+    let map = MetadataValue::Map(metadata.clone()).to_yaml();
+    let yaml = serde_yaml::to_string(&map).unwrap_or_default();
+    format!("---\n{yaml}---\n")
+}
+
+/// Error for a well-known metadata key whose value can not be read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataError {
+    pub key: String,
+    pub value: String,
+    /// what the value should look like
+    pub expected: &'static str,
+}
+
+impl fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` metadata `{}` is not {}",
+            self.key, self.value, self.expected
+        )
+    }
+}
+
+impl std::error::Error for MetadataError {}
+
+/// Number of people a recipe feeds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Servings {
+    Exact(u32),
+    /// `4-6`, the quantities of the recipe are for the lower bound
+    Range(u32, u32),
+    /// `2|4`, the quantities of the recipe are for the first one
+    Options(Vec<u32>),
+}
+
+impl Servings {
+    /// Number of servings the quantities of the recipe are written for
+    pub fn base(&self) -> u32 {
+        match self {
+            Servings::Exact(n) | Servings::Range(n, _) => *n,
+            Servings::Options(options) => options.first().copied().unwrap_or_default(),
+        }
+    }
+}
+
+/// keys of the servings metadata, in order of preference
+pub(crate) const SERVINGS_KEYS: &[&str] = &["servings", "serves"];
+
+fn parse_servings(text: &str) -> Option<Servings> {
+    let number = |s: &str| s.trim().parse::<u32>().ok().filter(|&n| n > 0);
+    if let Some((min, max)) = text.split_once('-') {
+        let (min, max) = (number(min)?, number(max)?);
+        return (min <= max).then_some(Servings::Range(min, max));
+    }
+    if text.contains('|') {
+        let options = text.split('|').map(number).collect::<Option<_>>()?;
+        return Some(Servings::Options(options));
+    }
+    number(text).map(Servings::Exact)
+}
+
+/// `1.5 hours`, `1h30m`, `1 hour 30 minutes`; a number alone is in minutes
+fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim().to_lowercase();
+    if let Ok(minutes) = text.parse::<f64>() {
+        return Duration::try_from_secs_f64(minutes * 60.0).ok();
+    }
+    let mut seconds = 0.0;
+    let mut rest = text.as_str();
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let number: f64 = rest[..number_len].parse().ok()?;
+        rest = rest[number_len..].trim_start();
+        let unit_len = rest
+            .find(|c: char| !c.is_alphabetic())
+            .unwrap_or(rest.len());
+        let unit_seconds = match &rest[..unit_len] {
+            // `m` is a metre in the units table
+            "m" => 60.0,
+            unit => {
+                let unit = UnitsTable::bundled().get(unit)?;
+                (unit.quantity == PhysicalQuantity::Time).then_some(unit.ratio)?
+            }
+        };
+        seconds += number * unit_seconds;
+        rest = rest[unit_len..].trim_start_matches([' ', ',']);
+        rest = rest.strip_prefix("and ").unwrap_or(rest);
+    }
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// Typed values of the metadata keys with a common meaning.
+///
+/// Every accessor gives `Ok(None)` when its key is missing, and an error
+/// when the value can not be read.
+#[derive(Debug, Clone, Copy)]
+pub struct KnownMetadata<'a> {
+    metadata: &'a IndexMap<String, MetadataValue>,
+}
+
+impl<'a> KnownMetadata<'a> {
+    pub fn new(metadata: &'a IndexMap<String, MetadataValue>) -> Self {
+        KnownMetadata { metadata }
+    }
+
+    /// value of the first key found, with the key
+    fn get(&self, keys: &[&'static str]) -> Option<(&'static str, &'a MetadataValue)> {
+        keys.iter()
+            .find_map(|&key| self.metadata.get(key).map(|value| (key, value)))
+    }
+
+    fn read<T>(
+        &self,
+        keys: &[&'static str],
+        expected: &'static str,
+        read: impl FnOnce(&MetadataValue) -> Option<T>,
+    ) -> Result<Option<T>, MetadataError> {
+        let Some((key, value)) = self.get(keys) else {
+            return Ok(None);
+        };
+        read(value).map(Some).ok_or_else(|| MetadataError {
+            key: key.to_string(),
+            value: value.to_string(),
+            expected,
+        })
+    }
+
+    /// `servings`: `4`, `4-6` or `2|4`
+    pub fn servings(&self) -> Result<Option<Servings>, MetadataError> {
+        self.read(
+            SERVINGS_KEYS,
+            "a number of servings like `4`, `4-6` or `2|4`",
+            |v| parse_servings(&v.to_string()),
+        )
+    }
+
+    fn duration(&self, keys: &[&'static str]) -> Result<Option<Duration>, MetadataError> {
+        self.read(keys, "a duration like `1.5 hours` or `1h30m`", |v| {
+            parse_duration(&v.to_string())
+        })
+    }
+
+    /// `time required`, or `time`
+    pub fn time_required(&self) -> Result<Option<Duration>, MetadataError> {
+        self.duration(&["time required", "time"])
+    }
+
+    /// `prep time`
+    pub fn prep_time(&self) -> Result<Option<Duration>, MetadataError> {
+        self.duration(&["prep time"])
+    }
+
+    /// `cook time`
+    pub fn cook_time(&self) -> Result<Option<Duration>, MetadataError> {
+        self.duration(&["cook time"])
+    }
+
+    /// `tags`, a list or comma separated
+    pub fn tags(&self) -> Result<Option<Vec<String>>, MetadataError> {
+        self.read(&["tags"], "a list of tags", |v| match v {
+            MetadataValue::List(tags) => tags
+                .iter()
+                .map(|t| t.as_str().map(|t| t.trim().to_string()))
+                .collect(),
+            MetadataValue::String(tags) => Some(
+                tags.split(',')
+                    .map(str::trim)
+                    .filter(|t| !t.is_empty())
+                    .map(String::from)
+                    .collect(),
+            ),
+            _ => None,
+        })
+    }
+
+    /// `source`, an http or https URL
+    pub fn source_url(&self) -> Result<Option<String>, MetadataError> {
+        self.read(&["source"], "an http or https URL", |v| {
+            let url = v.as_str()?.trim();
+            let host = url
+                .strip_prefix("https://")
+                .or_else(|| url.strip_prefix("http://"))?;
+            let valid = !host.is_empty() && !host.starts_with('/') && !url.contains(' ');
+            valid.then(|| url.to_string())
+        })
+    }
+
+    /// `author`
+    pub fn author(&self) -> Result<Option<String>, MetadataError> {
+        self.read(&["author"], "a name", |v| {
+            let author = v.as_str()?.trim();
+            (!author.is_empty()).then(|| author.to_string())
+        })
+    }
+
+    /// errors of every known key, to report them all at once
+    pub fn errors(&self) -> Vec<MetadataError> {
+        [
+            self.servings().err(),
+            self.time_required().err(),
+            self.prep_time().err(),
+            self.cook_time().err(),
+            self.tags().err(),
+            self.source_url().err(),
+            self.author().err(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+impl Recipe {
+    pub fn known_metadata(&self) -> KnownMetadata<'_> {
+        KnownMetadata::new(&self.metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_front_matter() {
+        let source = "---\ntitle: Bread\ntags: [baking, easy]\nservings: 4\n---\nKnead";
+        let front = front_matter(source).ok().flatten().unwrap();
+        assert_eq!(
+            front.values,
+            IndexMap::from([
+                ("title".to_string(), "Bread".into()),
+                (
+                    "tags".to_string(),
+                    MetadataValue::List(vec!["baking".into(), "easy".into()])
+                ),
+                ("servings".to_string(), MetadataValue::Number(4.0)),
+            ])
+        );
+        assert_eq!(&source[front.end..], "Knead");
+        assert_eq!(front.values["tags"].to_string(), "baking, easy");
+
+        assert!(front_matter("Knead\n---\n").ok().unwrap().is_none());
+        // a comment line without the closing `---`
+        assert!(front_matter("---\ntitle: Bread\nKnead")
+            .ok()
+            .unwrap()
+            .is_none());
+        let invalid = front_matter("---\ntitle: [Bread\n---\n").err().unwrap();
+        assert!(invalid.message.starts_with("invalid front matter"));
+        assert!(front_matter("---\n- a\n---\n").is_err());
+    }
+
+    #[test]
+    fn test_to_front_matter() {
+        let metadata = IndexMap::from([
+            ("servings".to_string(), MetadataValue::Number(4.0)),
+            ("ratio".to_string(), MetadataValue::Number(1.5)),
+            ("id".to_string(), "12".into()),
+        ]);
+        let written = to_front_matter(&metadata);
+        assert_eq!(written, "---\nservings: 4\nratio: 1.5\nid: '12'\n---\n");
+        assert_eq!(
+            front_matter(&written).ok().flatten().unwrap().values,
+            metadata
+        );
+    }
+
+    #[test]
+    fn test_servings() {
+        assert_eq!(parse_servings("4"), Some(Servings::Exact(4)));
+        assert_eq!(parse_servings("4 - 6"), Some(Servings::Range(4, 6)));
+        assert_eq!(parse_servings("2|4"), Some(Servings::Options(vec![2, 4])));
+        assert_eq!(parse_servings("6-4"), None);
+        assert_eq!(parse_servings("0"), None);
+        assert_eq!(parse_servings("a few"), None);
+    }
+
+    #[test]
+    fn test_duration() {
+        let minutes = |text: &str| parse_duration(text).map(|d| d.as_secs_f64() / 60.0);
+        assert_eq!(minutes("1.5 hours"), Some(90.0));
+        assert_eq!(minutes("1h30m"), Some(90.0));
+        assert_eq!(minutes("1 hour and 15 minutes"), Some(75.0));
+        assert_eq!(minutes("2 days"), Some(2880.0));
+        assert_eq!(minutes("45"), Some(45.0));
+        assert_eq!(minutes("90s"), Some(1.5));
+        assert_eq!(minutes("2 kg"), None);
+        assert_eq!(minutes("an hour"), None);
+        assert_eq!(minutes("1 fortnight"), None);
+    }
+
+    #[test]
+    fn test_known_metadata() {
+        let recipe = crate::parser::parse(String::from(
+            "---\n\
+            tags: [baking, easy]\n\
+            prep time: 20\n\
+            ---\n\
+            >> source: https://www.gimmesomeoven.com/baked-potato/\n\
+            >> time required: 1.5 hours\n\
+            >> servings: 4-6\n\
+            >> cook time: a while\n\
+            >> author: Jane",
+        ))
+        .unwrap();
+        let known = recipe.known_metadata();
+        assert_eq!(known.servings(), Ok(Some(Servings::Range(4, 6))));
+        assert_eq!(known.time_required(), Ok(Some(Duration::from_secs(5400))));
+        assert_eq!(known.prep_time(), Ok(Some(Duration::from_secs(1200))));
+        assert_eq!(
+            known.tags(),
+            Ok(Some(vec!["baking".to_string(), "easy".to_string()]))
+        );
+        assert_eq!(
+            known.source_url(),
+            Ok(Some(
+                "https://www.gimmesomeoven.com/baked-potato/".to_string()
+            ))
+        );
+        assert_eq!(known.author(), Ok(Some("Jane".to_string())));
+        let error = MetadataError {
+            key: "cook time".to_string(),
+            value: "a while".to_string(),
+            expected: "a duration like `1.5 hours` or `1h30m`",
+        };
+        assert_eq!(known.cook_time(), Err(error.clone()));
+        assert_eq!(known.errors(), vec![error]);
+
+        let metadata = IndexMap::from([
+            ("tags".to_string(), MetadataValue::from("soup, , winter")),
+            ("source".to_string(), MetadataValue::from("grandma")),
+        ]);
+        let known = KnownMetadata::new(&metadata);
+        assert_eq!(
+            known.tags(),
+            Ok(Some(vec!["soup".to_string(), "winter".to_string()]))
+        );
+        assert!(known.source_url().is_err());
+        assert_eq!(known.servings(), Ok(None));
+    }
+}
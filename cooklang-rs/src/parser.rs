@@ -1,55 +1,688 @@
-use nom::bytes::complete::{tag, take, take_until, take_while, take_while1};
+use nom::bytes::complete::{tag, take_while, take_while1};
 
+use indexmap::IndexMap;
 use nom::branch::alt;
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Range;
+
+use crate::borrowed::{
+    CookwareRef, IngredientRef, NoteRef, PartRef, QuantityRef, RecipeRef, TextRef, TimerRef,
+};
+use crate::cst::{tokens, RecipeBuilder, TokenRef};
+use crate::metadata::{front_matter, to_front_matter, MetadataValue};
 
 use nom::character::complete::space0;
 use nom::character::complete::space1;
-use nom::combinator::eof;
+use nom::character::complete::{digit0, digit1, satisfy};
 use nom::combinator::map_res;
+use nom::combinator::{all_consuming, recognize};
+use nom::combinator::{cut, eof, not, opt, verify};
 use nom::combinator::{map, value};
-use nom::multi::{fold_many0, fold_many1, many_till};
-use nom::sequence::{delimited, pair, preceded, separated_pair, tuple};
+use nom::multi::{many0_count, many_till};
+use nom::sequence::{delimited, pair, preceded, separated_pair, terminated, tuple};
 use nom::IResult;
 
-#[derive(Debug, PartialEq, Eq)]
+/// Location of an element in the parsed text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    /// byte offset of the first character
+    pub start: usize,
+    /// byte offset just after the last character
+    pub end: usize,
+    /// line of the first character, starting at 1
+    pub line: usize,
+    /// column of the first character in characters, starting at 1
+    pub column: usize,
+}
+
+/// Amount of an ingredient, a cookware or a timer
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Quantity {
+    /// no quantity given, read as "some" for ingredients and 1 for cookware
+    #[default]
+    Empty,
+    Integer(u64),
+    Decimal(f64),
+    /// exact fraction as written, numerator then denominator
+    Fraction(u64, u64),
+    /// free text like "a pinch"
+    Text(String),
+}
+
+impl Quantity {
+    /// Numeric value of the quantity, if it has one
+    pub fn value(&self) -> Option<f64> {
+        match self {
+            Quantity::Integer(i) => Some(*i as f64),
+            Quantity::Decimal(d) => Some(*d),
+            Quantity::Fraction(n, d) => Some(*n as f64 / *d as f64),
+            Quantity::Empty | Quantity::Text(_) => None,
+        }
+    }
+}
+
+pub(crate) fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+impl Quantity {
+    /// `numerator / denominator` in lowest terms, an integer when possible
+    pub(crate) fn ratio(numerator: u64, denominator: u64) -> Quantity {
+        let divisor = gcd(numerator, denominator).max(1);
+        match (numerator / divisor, denominator / divisor) {
+            (n, 1) => Quantity::Integer(n),
+            (n, d) => Quantity::Fraction(n, d),
+        }
+    }
+}
+
+impl From<&str> for Quantity {
+    /// Read a trimmed quantity, falling back to text when it is not a number
+    fn from(s: &str) -> Self {
+        QuantityRef::from(s).into_owned()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Quantity {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Quantity::Integer(i) => serializer.serialize_u64(*i),
+            Quantity::Decimal(d) => serializer.serialize_f64(*d),
+            Quantity::Fraction(n, d) => serializer.serialize_f64(*n as f64 / *d as f64),
+            Quantity::Empty | Quantity::Text(_) => serializer.collect_str(self),
+        }
+    }
+}
+
+/// an empty ingredient quantity is `"some"` in the canonical format
+#[cfg(feature = "serde")]
+mod ingredient_quantity {
+    use super::Quantity;
+    use serde::{Deserialize, Serialize};
+
+    pub fn serialize<S: serde::Serializer>(q: &Quantity, serializer: S) -> Result<S::Ok, S::Error> {
+        match q {
+            Quantity::Empty => serializer.serialize_str("some"),
+            q => q.serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Quantity, D::Error> {
+        match Quantity::deserialize(deserializer)? {
+            Quantity::Text(t) if t == "some" => Ok(Quantity::Empty),
+            q => Ok(q),
+        }
+    }
+}
+
+/// an empty cookware quantity is `1` in the canonical format
+#[cfg(feature = "serde")]
+fn serialize_cookware_quantity<S: serde::Serializer>(
+    q: &Quantity,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match q {
+        Quantity::Empty => serializer.serialize_u64(1),
+        q => serde::Serialize::serialize(q, serializer),
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Quantity {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct QuantityVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for QuantityVisitor {
+            type Value = Quantity;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a number or a string")
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Quantity, E> {
+                Ok(Quantity::Integer(v))
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<Quantity, E> {
+                u64::try_from(v)
+                    .map(Quantity::Integer)
+                    .map_err(|_| E::custom("negative quantity"))
+            }
+
+            fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<Quantity, E> {
+                Ok(Quantity::Decimal(v))
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Quantity, E> {
+                Ok(Quantity::from(v.trim()))
+            }
+        }
+
+        deserializer.deserialize_any(QuantityVisitor)
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Quantity::Empty => Ok(()),
+            Quantity::Integer(i) => write!(f, "{i}"),
+            // keep the point so that the text is read back as a decimal
+            Quantity::Decimal(d) if d.fract() == 0.0 => write!(f, "{d:.1}"),
+            Quantity::Decimal(d) => write!(f, "{d}"),
+            Quantity::Fraction(n, d) => write!(f, "{n}/{d}"),
+            Quantity::Text(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Metadata {
     pub key: String,
     pub value: String,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Ingredient {
     pub name: String,
-    pub quantity: String,
+    #[cfg_attr(feature = "serde", serde(with = "ingredient_quantity"))]
+    pub quantity: Quantity,
     pub units: String,
+    /// quantity written `{=...}`, kept as is when the recipe is scaled
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "std::ops::Not::not")
+    )]
+    pub fixed: bool,
+    /// `@?name`, the recipe works without it
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "std::ops::Not::not")
+    )]
+    pub optional: bool,
+    /// `@-name`, left out of the ingredients of the recipe
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "std::ops::Not::not")
+    )]
+    pub hidden: bool,
+    /// `@&name`, the same ingredient as an earlier `@name`
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "std::ops::Not::not")
+    )]
+    pub reference: bool,
+    /// `@./path{}`, the name is the path of another recipe
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "std::ops::Not::not")
+    )]
+    pub recipe_link: bool,
+    /// preparation written in parentheses after the amount, like `(finely chopped)`
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "String::is_empty")
+    )]
+    pub note: String,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub span: Span,
 }
 
-#[derive(Debug, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Cookware {
     pub name: String,
-    pub quantity: String,
+    #[cfg_attr(
+        feature = "serde",
+        serde(serialize_with = "serialize_cookware_quantity")
+    )]
+    pub quantity: Quantity,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub span: Span,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Timer {
     pub name: String,
-    pub quantity: String,
+    pub quantity: Quantity,
     pub units: String,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Text {
+    pub value: String,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub span: Span,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// A `> text` line, a remark that is not an instruction
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Note {
+    pub value: String,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub span: Span,
+}
 
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "lowercase"))]
 pub enum Part {
-    Metadata(Metadata),
     Cookware(Cookware),
     Timer(Timer),
     Ingredient(Ingredient),
-    Text(String),
+    Text(Text),
+    /// alone in its step
+    Note(Note),
+}
+
+impl Part {
+    pub fn span(&self) -> Span {
+        match self {
+            Part::Cookware(c) => c.span,
+            Part::Timer(t) => t.span,
+            Part::Ingredient(i) => i.span,
+            Part::Text(t) => t.span,
+            Part::Note(n) => n.span,
+        }
+    }
+}
+
+/// A line of instructions
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Step {
+    pub parts: Vec<Part>,
+    /// from the start of the first part to the end of the last one
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub span: Span,
+}
+
+impl Step {
+    /// the step is a note, and not an instruction to number
+    pub fn is_note(&self) -> bool {
+        matches!(self.parts.as_slice(), [Part::Note(_)])
+    }
+}
+
+/// A `== name ==` header and the steps following it, up to the next header
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Section {
+    /// name between the `=` signs, empty for the steps before the first header
+    pub name: String,
+    /// steps of the section as ranges of `Recipe::steps`, one range per
+    /// paragraph. Paragraphs are separated by blank lines.
+    pub paragraphs: Vec<Range<usize>>,
+    /// the header line, empty for the steps before the first header
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub span: Span,
+}
+
+impl Section {
+    /// steps of every paragraph of the section
+    pub fn steps<'r>(&self, recipe: &'r Recipe) -> &'r [Step] {
+        match (self.paragraphs.first(), self.paragraphs.last()) {
+            (Some(first), Some(last)) => &recipe.steps[first.start..last.end],
+            _ => &[],
+        }
+    }
+}
+
+/// A parsed recipe, shaped like the `result` of the canonical tests
+///
+/// With the `serde` feature, a recipe is serialized as
+///
+/// ```json
+/// {
+///   "metadata": {"servings": "2"},
+///   "steps": [
+///     [
+///       {"type": "text", "value": "Fry"},
+///       {"type": "ingredient", "name": "onion", "quantity": 1, "units": ""},
+///       {"type": "text", "value": "in the"},
+///       {"type": "cookware", "name": "pan", "quantity": 1},
+///       {"type": "text", "value": "for"},
+///       {"type": "timer", "name": "", "quantity": 0.5, "units": "hour"}
+///     ]
+///   ],
+///   "sections": [{"name": "", "paragraphs": [{"start": 0, "end": 1}]}]
+/// }
+/// ```
+///
+/// As in the canonical tests, numeric quantities are JSON numbers, fractions
+/// included, and free text quantities are strings. An empty quantity is
+/// `"some"` for an ingredient, `1` for a cookware and `""` for a timer. So
+/// reading the JSON back gives decimals instead of fractions, and `1` for
+/// cookware without a quantity.
+/// Metadata from the front matter keep their YAML type. Spans and warnings
+/// are not serialized.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Recipe {
+    /// metadata in the order of the file, the last value wins for repeated
+    /// `>>` keys. Values of the front matter win over `>>` metadata.
+    pub metadata: IndexMap<String, MetadataValue>,
+    pub steps: Vec<Step>,
+    /// grouping of the steps, every step is in exactly one section
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub sections: Vec<Section>,
+    /// problems that do not prevent reading the recipe
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub warnings: Vec<Warning>,
+}
+
+impl Recipe {
+    fn parts(&self) -> impl Iterator<Item = &Part> {
+        self.steps.iter().flat_map(|step| step.parts.iter())
+    }
+
+    /// Ingredients of the recipe, once per name, in order of first use.
+    /// Hidden ingredients are left out.
+    pub fn ingredients(&self) -> Vec<&Ingredient> {
+        let mut ingredients: Vec<&Ingredient> = Vec::new();
+        for part in self.parts() {
+            if let Part::Ingredient(i) = part {
+                if !i.hidden && !ingredients.iter().any(|known| known.name == i.name) {
+                    ingredients.push(i);
+                }
+            }
+        }
+        ingredients
+    }
+
+    /// Cookware of the recipe, once per name, in order of first use
+    pub fn cookware(&self) -> Vec<&Cookware> {
+        let mut cookware: Vec<&Cookware> = Vec::new();
+        for part in self.parts() {
+            if let Part::Cookware(c) = part {
+                if !cookware.iter().any(|known| known.name == c.name) {
+                    cookware.push(c);
+                }
+            }
+        }
+        cookware
+    }
+
+    /// Timers of the recipe, without repeating identical ones, in order of first use
+    pub fn timers(&self) -> Vec<&Timer> {
+        let mut timers: Vec<&Timer> = Vec::new();
+        for part in self.parts() {
+            if let Part::Timer(t) = part {
+                if !timers.iter().any(|known| {
+                    known.name == t.name && known.quantity == t.quantity && known.units == t.units
+                }) {
+                    timers.push(t);
+                }
+            }
+        }
+        timers
+    }
+}
+
+/// A line of the recipe file, or the front matter
+pub(crate) enum Line<'a> {
+    FrontMatter(IndexMap<String, MetadataValue>, Span),
+    Metadata(Metadata, Span),
+    Section(Cow<'a, str>, Span),
+    /// parts as written, texts separated by comments are not merged yet
+    Step(Vec<PartRef<'a>>),
+    /// text skipped after an error
+    Error(Span),
+}
+
+/// Computes spans from byte offsets in the parsed text
+pub(crate) struct Locator<'a> {
+    source: &'a str,
+    /// byte offset of the start of every line
+    line_starts: Vec<usize>,
+}
+
+impl<'a> Locator<'a> {
+    pub(crate) fn new(source: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Locator {
+            source,
+            line_starts,
+        }
+    }
+
+    pub(crate) fn span(&self, start: usize, end: usize) -> Span {
+        let line = self.line_starts.partition_point(|&s| s <= start);
+        let column = self.source[self.line_starts[line - 1]..start]
+            .chars()
+            .count()
+            + 1;
+        Span {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    /// span of the text consumed between `input` and `rest`, surrounding spaces
+    /// and tabs excluded, other white space can be part of names
+    fn consumed(&self, input: &str, rest: &str) -> Span {
+        let consumed = &input[..input.len() - rest.len()];
+        let start = self.source.len() - input.len();
+        let end = self.source.len() - rest.len();
+        let spaces = [' ', '\t', '\r'];
+        self.span(
+            start + consumed.len() - consumed.trim_start_matches(spaces).len(),
+            end - (consumed.len() - consumed.trim_end_matches(spaces).len()),
+        )
+    }
+
+    /// run `parser` and set the span of the part it returns
+    fn located<'i>(
+        &'i self,
+        mut parser: impl FnMut(&'a str) -> IResult<&'a str, Option<PartRef<'a>>> + 'i,
+    ) -> impl FnMut(&'a str) -> IResult<&'a str, Option<PartRef<'a>>> + 'i {
+        move |input| {
+            let (rest, mut part) = parser(input)?;
+            if let Some(part) = &mut part {
+                part.set_span(self.consumed(input, rest));
+            }
+            Ok((rest, part))
+        }
+    }
+}
+
+/// Construct the parser was expecting when it failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expected {
+    /// a name after `@`, `#` or `~`
+    Name,
+    /// an amount between braces, for anonymous timers
+    Amount,
+    /// a `}` closing an amount
+    ClosingBrace,
+    /// a `:` followed by a value after a `>>` metadata key
+    MetadataValue,
+    /// a YAML map between `---` lines
+    FrontMatter,
+    /// an `@name` ingredient before its `@&name` reference
+    Definition,
+    /// a `-]` closing a `[-` block comment
+    ClosingComment,
+}
+
+/// Error returned by [`parse`] when the input is not a valid recipe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// line of the error, starting at 1
+    pub line: usize,
+    /// column of the error in characters, starting at 1
+    pub column: usize,
+    /// byte offset of the error
+    pub offset: usize,
+    pub expected: Expected,
+    /// short human readable description
+    pub message: String,
+}
+
+impl ParseError {
+    /// Build the error for a parse that stopped at `rest`, a suffix of the
+    /// source of `locator`
+    fn new(locator: &Locator, rest: &str) -> Self {
+        let input = locator.source;
+        let failed_at = input.len() - rest.len();
+        let line_start = input[..failed_at].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = input[failed_at..]
+            .find('\n')
+            .map_or(input.len(), |i| failed_at + i);
+        let mut offset = failed_at + (rest.len() - rest.trim_start_matches([' ', '\t']).len());
+        let current = &input[offset..line_end];
+
+        let (expected, message) = if current.starts_with("[-") {
+            (
+                Expected::ClosingComment,
+                "unclosed `[-` comment".to_string(),
+            )
+        } else if input[line_start..].starts_with(">>") {
+            offset = line_start;
+            (
+                Expected::MetadataValue,
+                "expected `:` after metadata key".to_string(),
+            )
+        } else if let Some(after) = current.strip_prefix('{') {
+            if after.contains('}') {
+                (
+                    Expected::Name,
+                    "amount is not attached to an ingredient, cookware or timer".to_string(),
+                )
+            } else {
+                (Expected::ClosingBrace, "unclosed `{`".to_string())
+            }
+        } else if current.starts_with("~{") {
+            offset += 1;
+            (Expected::ClosingBrace, "unclosed `{`".to_string())
+        } else if current.starts_with('~') {
+            (
+                Expected::Amount,
+                "expected a name or an amount after `~`".to_string(),
+            )
+        } else {
+            let marker = current.chars().next().unwrap_or(' ');
+            (Expected::Name, format!("expected a name after `{marker}`"))
+        };
+
+        Self::at(locator, offset, expected, message)
+    }
+
+    pub(crate) fn at(
+        locator: &Locator,
+        offset: usize,
+        expected: Expected,
+        message: String,
+    ) -> Self {
+        let Span { line, column, .. } = locator.span(offset, offset);
+        ParseError {
+            line,
+            column,
+            offset,
+            expected,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A problem reported by [`parse_recovering`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// the text around the error was skipped
+    Error(ParseError),
+    Warning(Warning),
+}
+
+impl Diagnostic {
+    /// byte offset of the problem
+    pub fn offset(&self) -> usize {
+        match self {
+            Diagnostic::Error(e) => e.offset,
+            Diagnostic::Warning(w) => w.span.start,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Diagnostic::Error(e) => &e.message,
+            Diagnostic::Warning(w) => &w.message,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::Error(e) => write!(f, "{}:{}: error: {}", e.line, e.column, e.message),
+            Diagnostic::Warning(w) => write!(
+                f,
+                "{}:{}: warning: {}",
+                w.span.line, w.span.column, w.message
+            ),
+        }
+    }
+}
+
+/// A problem in a recipe that can still be read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub span: Span,
+    /// short human readable description
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}",
+            self.span.line, self.span.column, self.message
+        )
+    }
 }
 
 /// block comments = "[", "-", ? any character except "-" followed by "]" ?, "-", "]" ;
+/// a `[-` without its `-]` fails the parse, instead of being read as text
 fn block_comment(input: &str) -> IResult<&str, &str> {
-    value("", delimited(tag("[-"), take_until("-]"), tag("-]")))(input)
+    let (body, _) = tag("[-")(input)?;
+    match body.find("-]") {
+        Some(end) => Ok((&body[end + 2..], "")),
+        None => Err(nom::Err::Failure(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::TakeUntil,
+        ))),
+    }
 }
 
 /// line comments       = "-", "-", text item, new line character ;
@@ -58,20 +691,39 @@ fn line_comment(input: &str) -> IResult<&str, &str> {
 }
 
 /// comments
-fn comment(input: &str) -> IResult<&str, &str> {
+pub(crate) fn comment(input: &str) -> IResult<&str, &str> {
     alt((block_comment, line_comment))(input)
 }
 
-pub fn remove_comment(input: String) -> String {
-    let a = fold_many1(
-        alt((comment, take(1u8))),
-        String::new,
-        |mut string, fragment| {
-            string.push_str(fragment);
-            string
-        },
-    )(&input);
-    a.unwrap().1
+/// Remove the comments of a text, in a single pass. [`parse`] skips comments
+/// by itself, so that spans refer to the original text.
+///
+/// Like [`parse`], this fails with [`Expected::ClosingComment`] on a `[-`
+/// without its `-]`.
+pub fn remove_comment(input: String) -> Result<String, ParseError> {
+    let mut output = String::new();
+    // start of the text not copied to `output` yet
+    let mut kept = 0;
+    let mut at = 0;
+    while let Some(found) = input[at..].find(['-', '[']) {
+        let start = at + found;
+        match comment(&input[start..]) {
+            Ok((rest, _)) => {
+                output.push_str(&input[kept..start]);
+                kept = input.len() - rest.len();
+                at = kept;
+            }
+            Err(nom::Err::Failure(e)) => {
+                return Err(ParseError::new(&Locator::new(&input), e.input))
+            }
+            Err(_) => at = start + 1,
+        }
+    }
+    if kept == 0 {
+        return Ok(input);
+    }
+    output.push_str(&input[kept..]);
+    Ok(output)
 }
 
 /// spaces + one or many endline chars
@@ -79,192 +731,582 @@ fn end_line(input: &str) -> IResult<&str, &str> {
     preceded(space0, take_while1(|c| "\n\r".contains(c)))(input)
 }
 
+/// text up to one of the `stop` characters or to the start of a comment,
+/// failing on an unclosed comment
+fn until_comment_or<'a>(stop: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    move |input: &'a str| {
+        for (i, c) in input.char_indices() {
+            if stop.contains(c) {
+                return Ok((&input[i..], &input[..i]));
+            }
+            if "-[".contains(c) {
+                match comment(&input[i..]) {
+                    Ok(_) => return Ok((&input[i..], &input[..i])),
+                    Err(nom::Err::Failure(e)) => return Err(nom::Err::Failure(e)),
+                    Err(_) => {}
+                }
+            }
+        }
+        Ok((&input[input.len()..], input))
+    }
+}
+
+/// text up to the end of the line or to a line comment, without the block
+/// comments inside it
+fn until_line_end(input: &str) -> IResult<&str, Cow<'_, str>> {
+    let (mut rest, first) = until_comment_or("\n")(input)?;
+    let mut value = Cow::Borrowed(first);
+    while let Ok((after, _)) = block_comment(rest) {
+        let (after, text) = until_comment_or("\n")(after)?;
+        value.to_mut().push_str(text);
+        rest = after;
+    }
+    Ok((rest, value))
+}
+
+/// `value` without the leading and trailing characters matching `pattern`
+fn trim(value: Cow<'_, str>, pattern: fn(char) -> bool) -> Cow<'_, str> {
+    match value {
+        Cow::Borrowed(value) => Cow::Borrowed(value.trim_matches(pattern)),
+        Cow::Owned(value) => Cow::Owned(value.trim_matches(pattern).to_string()),
+    }
+}
+
 /// at least one space of tab
-fn space(input: &str) -> IResult<&str, String> {
-    map(space1, |c: &str| c.to_string())(input)
+fn space(input: &str) -> IResult<&str, &str> {
+    space1(input)
 }
 
 /// word      = { text item - white space - punctuation character }- ;
-fn word(input: &str) -> IResult<&str, String> {
-    map(take_while1(|c| !"~@#{ \t\n\r.,;".contains(c)), |c: &str| {
-        c.to_string()
-    })(input)
+fn word(input: &str) -> IResult<&str, &str> {
+    verify(until_comment_or("~@#{ \t\n\r.,;"), |c: &str| !c.is_empty())(input)
 }
 
-fn multiword(input: &str) -> IResult<&str, String> {
-    map(
-        tuple((
-            word,
-            fold_many0(tuple((space, word)), String::new, |mut string, (s1, s2)| {
-                string.push_str(&s1);
-                string.push_str(&s2);
-                string
-            }),
-        )),
-        |(s1, s2)| {
-            let mut s = s1;
-            s.push_str(&s2);
-            s
-        },
+/// words separated by spaces, as written
+fn multiword(input: &str) -> IResult<&str, &str> {
+    recognize(pair(word, many0_count(pair(space, word))))(input)
+}
+
+/// units    = { text item - "}" }- ;
+/// spaces are trimmed
+fn unit(input: &str) -> IResult<&str, &str> {
+    map(take_while(|c| !"\n\r}".contains(c)), str::trim)(input)
+}
+
+/// integer = "0" | non zero digit, { digit } ;
+/// so that "01/2" is not read as a fraction
+fn integer(input: &str) -> IResult<&str, u64> {
+    map_res(
+        recognize(alt((
+            tag("0"),
+            recognize(pair(satisfy(|c| ('1'..='9').contains(&c)), digit0)),
+        ))),
+        str::parse,
     )(input)
 }
 
-fn trim_spaces(s: &str) -> String {
-    s.to_string().trim().to_string()
+/// decimal = integer, ".", digit, { digit } ;
+fn decimal(input: &str) -> IResult<&str, f64> {
+    map_res(recognize(tuple((integer, tag("."), digit1))), str::parse)(input)
 }
 
-/// units    = { text item - "}" }- ;
-/// spaces are trimmed
-fn unit(input: &str) -> IResult<&str, String> {
-    map(take_while(|c| !"\n\r}".contains(c)), trim_spaces)(input)
+/// fraction = integer, [ space ], "/", [ space ], integer ;
+fn fraction(input: &str) -> IResult<&str, (u64, u64)> {
+    verify(
+        separated_pair(integer, delimited(space0, tag("/"), space0), integer),
+        |&(_, d)| d != 0,
+    )(input)
 }
 
-/// quantity = { text item - "%" - "}" }- ;
-/// spaces are trimmed
-fn quantity(input: &str) -> IResult<&str, String> {
-    map(take_while(|c| !"\n\r}%".contains(c)), trim_spaces)(input)
+/// number = fraction | decimal | integer ;
+pub(crate) fn number(input: &str) -> IResult<&str, QuantityRef<'_>> {
+    all_consuming(alt((
+        map(fraction, |(n, d)| QuantityRef::Fraction(n, d)),
+        map(decimal, QuantityRef::Decimal),
+        map(integer, QuantityRef::Integer),
+    )))(input)
+}
+
+/// quantity = [ "=" ], { text item - "%" - "}" }- ;
+/// a leading "=" marks a quantity that does not scale, spaces are trimmed
+fn quantity(input: &str) -> IResult<&str, (bool, QuantityRef<'_>)> {
+    map(take_while(|c| !"\n\r}%".contains(c)), |q: &str| {
+        match q.trim().strip_prefix('=') {
+            Some(fixed) => (true, QuantityRef::from(fixed.trim_start())),
+            None => (false, QuantityRef::from(q.trim())),
+        }
+    })(input)
 }
 
+/// fixed, quantity and units of an item
+type Amount<'a> = (bool, QuantityRef<'a>, &'a str);
+
 /// amount   = {quantity | ( quantity, "%", units )} ;
-fn amount(input: &str) -> IResult<&str, (String, String)> {
-    delimited(
-        tag("{"),
-        alt((
-            separated_pair(quantity, tag("%"), unit),
-            map(quantity, |v| (v, "".to_string())),
-        )),
-        tag("}"),
+fn amount(input: &str) -> IResult<&str, Amount<'_>> {
+    map(
+        delimited(
+            tag("{"),
+            alt((
+                separated_pair(quantity, tag("%"), unit),
+                map(quantity, |v| (v, "")),
+            )),
+            tag("}"),
+        ),
+        |((fixed, quantity), units)| (fixed, quantity, units),
+    )(input)
+}
+
+fn multi_word_item(input: &str) -> IResult<&str, (&str, Amount<'_>)> {
+    pair(multiword, amount)(input)
+}
+
+/// ingredient note      = "(", { text item - ")" }, ")" ;
+fn ingredient_note(input: &str) -> IResult<&str, &str> {
+    map(
+        delimited(tag("("), take_while(|c| c != ')' && c != '\n'), tag(")")),
+        str::trim,
     )(input)
 }
 
-fn multi_word_item(input: &str) -> IResult<&str, (String, String, String)> {
-    map(pair(multiword, amount), |(word, (quantity, unit))| {
-        (word, quantity, unit)
+/// modifiers            = { "?" | "-" | "&" } ;
+/// each modifier at most once, `--` is a comment
+fn modifiers(input: &str) -> IResult<&str, &str> {
+    verify(take_while(|c| "?-&".contains(c)), |m: &str| {
+        m.char_indices().all(|(i, c)| !m[..i].contains(c))
     })(input)
 }
 
-/// one word ingredient  = "@", ( word,                     [ "{", [ amount ], "}" ] ) ;
-fn ingredient(input: &str) -> IResult<&str, Part> {
-    preceded(
-        preceded(space0, tag("@")),
-        alt((
-            map(multi_word_item, |(word, quantity, units)| {
-                Part::Ingredient(Ingredient {
-                    name: word,
-                    quantity,
-                    units,
-                })
-            }),
-            map(word, |word| {
-                Part::Ingredient(Ingredient {
-                    name: word,
-                    quantity: String::from(""),
-                    units: String::from(""),
-                })
-            }),
-        )),
+/// recipe link          = ( "./" | "../" ), path, { white space, path }, "{", [ amount ], "}"
+///                      | ( "./" | "../" ), path ;
+/// like names, a path takes spaces only when braces follow
+fn recipe_link(input: &str) -> IResult<&str, (&str, Amount<'_>)> {
+    let path = || take_while1(|c: char| !" \t\n\r{@#~".contains(c));
+    let start = || alt((tag("./"), tag("../")));
+    alt((
+        pair(
+            recognize(tuple((start(), path(), many0_count(pair(space, path()))))),
+            amount,
+        ),
+        map(recognize(pair(start(), path())), |path| {
+            (path, (false, QuantityRef::Empty, ""))
+        }),
+    ))(input)
+}
+
+/// one word ingredient  = "@", modifiers, ( word,                     [ "{", [ amount ], "}", [ ingredient note ] ] ) ;
+fn ingredient(input: &str) -> IResult<&str, PartRef<'_>> {
+    map(
+        preceded(
+            preceded(space0, tag("@")),
+            tuple((
+                modifiers,
+                alt((
+                    map(recipe_link, |item| (item, true)),
+                    map(multi_word_item, |item| (item, false)),
+                    map(word, |word| {
+                        ((word, (false, QuantityRef::Empty, "")), false)
+                    }),
+                )),
+                opt(ingredient_note),
+            )),
+        ),
+        |(modifiers, ((name, (fixed, quantity, units)), recipe_link), note)| {
+            PartRef::Ingredient(IngredientRef {
+                name,
+                quantity,
+                units,
+                fixed,
+                optional: modifiers.contains('?'),
+                hidden: modifiers.contains('-'),
+                reference: modifiers.contains('&'),
+                recipe_link,
+                note: note.unwrap_or_default(),
+                ..Default::default()
+            })
+        },
     )(input)
 }
 
-fn cookware(input: &str) -> IResult<&str, Part> {
+fn cookware(input: &str) -> IResult<&str, PartRef<'_>> {
     preceded(
         preceded(space0, tag("#")),
         alt((
-            map(multi_word_item, |(word, quantity, _unit)| {
-                Part::Cookware(Cookware {
+            map(multi_word_item, |(word, (_fixed, quantity, _unit))| {
+                PartRef::Cookware(CookwareRef {
                     name: word,
                     quantity,
+                    ..Default::default()
                 })
             }),
             map(word, |word| {
-                Part::Cookware(Cookware {
+                PartRef::Cookware(CookwareRef {
                     name: word,
-                    quantity: "".to_string(),
+                    ..Default::default()
                 })
             }),
         )),
     )(input)
 }
 
-fn timer(input: &str) -> IResult<&str, Part> {
+fn timer(input: &str) -> IResult<&str, PartRef<'_>> {
     preceded(
         preceded(space0, tag("~")),
         alt((
-            map(multi_word_item, |(word, quantity, units)| {
-                Part::Timer(Timer {
+            map(multi_word_item, |(word, (_fixed, quantity, units))| {
+                PartRef::Timer(TimerRef {
                     name: word,
                     quantity,
                     units,
+                    ..Default::default()
                 })
             }),
             map(word, |word| {
-                Part::Timer(Timer {
+                PartRef::Timer(TimerRef {
                     name: word,
-                    quantity: String::from(""),
-                    units: String::from(""),
+                    ..Default::default()
                 })
             }),
-            map(amount, |(quantity, units)| {
-                Part::Timer(Timer {
-                    name: "".to_string(),
+            map(amount, |(_fixed, quantity, units)| {
+                PartRef::Timer(TimerRef {
                     quantity,
                     units,
+                    ..Default::default()
                 })
             }),
         )),
     )(input)
 }
 
-fn metadata_tuple(input: &str) -> IResult<&str, (&str, &str, &str, &str)> {
+/// a line starting with `>>` must be metadata
+fn metadata_tuple(input: &str) -> IResult<&str, (&str, &str, &str, Cow<'_, str>)> {
     tuple((
         tag(">>"),
-        take_while(|c| c != ':'),
-        tag(":"),
-        take_while(|c| c != '\n'),
+        take_while(|c| c != ':' && c != '\n'),
+        cut(tag(":")),
+        until_line_end,
     ))(input)
 }
 
-fn metadata(input: &str) -> IResult<&str, Part> {
-    map(metadata_tuple, |(_, k, _, v)| {
-        Part::Metadata(Metadata {
-            key: trim_spaces(k),
-            value: trim_spaces(v),
-        })
+fn metadata(input: &str) -> IResult<&str, Metadata> {
+    map(metadata_tuple, |(_, k, _, v)| Metadata {
+        key: k.trim().to_string(),
+        value: v.trim().to_string(),
     })(input)
 }
 
-fn text(input: &str) -> IResult<&str, Part> {
-    map_res(take_while(|c| !"~@#{\n\r".contains(c)), |w: &str| {
-        let s = trim_spaces(w);
+fn text(input: &str) -> IResult<&str, PartRef<'_>> {
+    map_res(until_comment_or("~@#{\n\r"), |w: &str| {
+        let s = w.trim();
         if s.is_empty() {
             return Err("no word");
         }
-        Ok(Part::Text(s))
+        Ok(PartRef::Text(TextRef {
+            value: Cow::Borrowed(s),
+            ..Default::default()
+        }))
+    })(input)
+}
+
+/// section    = "=", { "=" }, name, { "=" } ;
+fn section(input: &str) -> IResult<&str, Cow<'_, str>> {
+    map(preceded(pair(space0, tag("=")), until_line_end), |name| {
+        trim(name, |c| c == '=' || c.is_whitespace())
     })(input)
 }
 
-pub fn parse(input: String) -> Vec<Vec<Part>> {
-    let pre_processed = remove_comment(input);
-    let a = many_till(
+/// note       = ">", text item, new line character ;
+fn note(input: &str) -> IResult<&str, PartRef<'_>> {
+    map(
+        preceded(
+            pair(space0, terminated(tag(">"), not(tag(">")))),
+            until_line_end,
+        ),
+        |value| {
+            PartRef::Note(NoteRef {
+                value: trim(value, char::is_whitespace),
+                ..Default::default()
+            })
+        },
+    )(input)
+}
+
+/// element of a step, `None` for comments
+fn step_part(input: &str) -> IResult<&str, Option<PartRef<'_>>> {
+    alt((
+        map(preceded(space0, comment), |_| None),
+        map(alt((timer, cookware, ingredient, text)), Some),
+    ))(input)
+}
+
+/// Lines of `source`, with the spans of their elements, and the errors of
+/// the lines that could not be read. Parsing resumes on the line after an
+/// error, the skipped text is kept as a [`Line::Error`].
+pub(crate) fn lines(source: &str) -> (Vec<Line<'_>>, Vec<ParseError>) {
+    let locator = Locator::new(source);
+    let mut lines = Vec::new();
+    let mut errors = Vec::new();
+    let body = match front_matter(source) {
+        Ok(Some(f)) => {
+            let end = source[..f.end].trim_end().len();
+            lines.push(Line::FrontMatter(f.values, locator.span(0, end)));
+            &source[f.end..]
+        }
+        Ok(None) => source,
+        Err(e) => {
+            let end = source[..e.end].trim_end().len();
+            lines.push(Line::Error(locator.span(0, end)));
+            errors.push(ParseError::at(
+                &locator,
+                e.offset,
+                Expected::FrontMatter,
+                e.message,
+            ));
+            &source[e.end..]
+        }
+    };
+    let mut line = alt((
+        |input| {
+            let (rest, m) = metadata(input)?;
+            Ok((rest, Line::Metadata(m, locator.consumed(input, rest))))
+        },
+        |input| {
+            let (rest, mut part) = note(input)?;
+            part.set_span(locator.consumed(input, rest));
+            Ok((rest, Line::Step(vec![part])))
+        },
+        |input| {
+            let (rest, name) = section(input)?;
+            Ok((rest, Line::Section(name, locator.consumed(input, rest))))
+        },
         map(
-            alt((
-                map(metadata, |p| (vec![p], "a")),
-                many_till(
-                    alt((timer, cookware, ingredient, text)),
-                    alt((end_line, eof)),
-                ),
-            )),
-            |a| a.0,
+            many_till(locator.located(step_part), alt((end_line, eof))),
+            |(parts, _)| Line::Step(parts.into_iter().flatten().collect()),
         ),
-        eof,
-    )(pre_processed.trim());
+    ));
 
-    a.unwrap()
-        .1
-         .0
-        .into_iter()
-        .filter(|p| !p.is_empty())
-        .collect()
+    let mut input = body;
+    while !input.is_empty() {
+        let failed_at = match line(input) {
+            Ok((rest, line)) if rest.len() < input.len() => {
+                lines.push(line);
+                input = rest;
+                continue;
+            }
+            Ok(_) => input,
+            Err(nom::Err::Error(e) | nom::Err::Failure(e)) => e.input,
+            Err(nom::Err::Incomplete(_)) => "",
+        };
+        let error = ParseError::new(&locator, failed_at);
+        // skip the rest of the line of the error, at least one character, or
+        // the rest of the source after an unclosed comment
+        let offset = source.len() - input.len();
+        let failed_at = source.len() - failed_at.len();
+        let line_end = match error.expected {
+            Expected::ClosingComment => source.len(),
+            _ => source[failed_at..]
+                .find('\n')
+                .map_or(source.len(), |i| failed_at + i),
+        };
+        errors.push(error);
+        let end = source[..line_end]
+            .trim_end_matches('\r')
+            .len()
+            .max(offset + input.chars().next().map_or(0, char::len_utf8));
+        let start = (offset + input.len() - input.trim_start_matches([' ', '\t']).len()).min(end);
+        lines.push(Line::Error(locator.span(start, end)));
+        input = &source[end..];
+    }
+    (lines, errors)
+}
+
+/// Parse a recipe, see [`parse_borrowed`] to keep the text of the recipe
+pub fn parse(input: String) -> Result<Recipe, ParseError> {
+    parse_borrowed(&input).map(RecipeRef::into_owned)
+}
+
+/// the recipe of the tokens of `input`, and its errors
+fn build<'a>(input: &'a str, tokens: Vec<TokenRef<'a>>) -> (RecipeRef<'a>, Vec<ParseError>) {
+    let mut builder = RecipeBuilder::new();
+    for token in tokens {
+        builder.push(token);
+    }
+    builder.finish(|| input.to_string())
+}
+
+/// Parse a recipe without copying its text, see [`borrowed`](crate::borrowed)
+pub fn parse_borrowed(input: &str) -> Result<RecipeRef<'_>, ParseError> {
+    let (tokens, errors) = tokens(input);
+    if let Some(e) = errors.into_iter().next() {
+        return Err(e);
+    }
+    let (recipe, errors) = build(input, tokens);
+    match errors.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(recipe),
+    }
+}
+
+/// Parse a recipe, skipping the lines with errors instead of failing.
+///
+/// The recipe holds everything that could be read, and the diagnostics are
+/// the errors of the skipped lines and the warnings of the recipe, in the
+/// order of the source.
+pub fn parse_recovering(input: &str) -> (Recipe, Vec<Diagnostic>) {
+    let (tokens, mut errors) = tokens(input);
+    let (recipe, recipe_errors) = build(input, tokens);
+    let recipe = recipe.into_owned();
+    errors.extend(recipe_errors);
+    let mut diagnostics: Vec<_> = errors.into_iter().map(Diagnostic::Error).collect();
+    diagnostics.extend(recipe.warnings.iter().cloned().map(Diagnostic::Warning));
+    diagnostics.sort_by_key(Diagnostic::offset);
+    (recipe, diagnostics)
+}
+
+/// `@`, `#` or `~` followed by the name and the amount of an item.
+/// Braces are omitted when the name is a single word without amount.
+fn write_item(
+    out: &mut String,
+    marker: &str,
+    name: &str,
+    (fixed, quantity, units): (bool, &Quantity, &str),
+) {
+    out.push_str(marker);
+    out.push_str(name);
+    let single_word = matches!(word(name), Ok(("", _)));
+    if !single_word || fixed || *quantity != Quantity::Empty || !units.is_empty() {
+        out.push('{');
+        let quantity = quantity.to_string();
+        // a leading `=` is read as the fixed marker
+        if fixed || quantity.starts_with('=') {
+            out.push('=');
+        }
+        out.push_str(&quantity);
+        if !units.is_empty() {
+            out.push('%');
+            out.push_str(units);
+        }
+        out.push('}');
+    }
+}
+
+/// `text` with an empty comment inside each `--` and `[-`, which would
+/// otherwise start a comment: they come from texts joined across comments
+fn escape_comments(text: &str) -> Cow<'_, str> {
+    if !text.contains("--") && !text.contains("[-") {
+        return Cow::Borrowed(text);
+    }
+    let mut escaped = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        escaped.push(c);
+        if matches!((c, chars.peek()), ('-' | '[', Some('-'))) {
+            escaped.push_str("[--]");
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// a step on one line, without the line ending
+fn write_step(out: &mut String, step: &Step) {
+    // a text starting like metadata, a note or a header is only read as text
+    // after something else on its line, an empty comment here
+    if matches!(step.parts.first(), Some(Part::Text(t)) if t.value.starts_with(['>', '='])) {
+        out.push_str("[--] ");
+    }
+    for (i, part) in step.parts.iter().enumerate() {
+        // words end at punctuation, other parts need a space to be told apart
+        let glued = matches!(part, Part::Text(t) if t.value.starts_with(['.', ',', ';']));
+        if i > 0 && !glued {
+            out.push(' ');
+        }
+        write_part(out, part);
+    }
+}
+
+/// a part as it is written in a step
+pub(crate) fn write_part(out: &mut String, part: &Part) {
+    match part {
+        Part::Ingredient(i) => {
+            let mut marker = String::from("@");
+            for (set, modifier) in [(i.optional, '?'), (i.hidden, '-'), (i.reference, '&')] {
+                if set {
+                    marker.push(modifier);
+                }
+            }
+            write_item(out, &marker, &i.name, (i.fixed, &i.quantity, &i.units));
+            if !i.note.is_empty() {
+                // the note needs the braces of the amount
+                if !out.ends_with('}') {
+                    out.push_str("{}");
+                }
+                out.push_str(&format!("({})", i.note));
+            }
+        }
+        Part::Cookware(c) => write_item(out, "#", &c.name, (false, &c.quantity, "")),
+        Part::Timer(t) => write_item(out, "~", &t.name, (false, &t.quantity, &t.units)),
+        Part::Text(t) => out.push_str(&escape_comments(&t.value)),
+        Part::Note(n) => {
+            out.push_str("> ");
+            out.push_str(&escape_comments(&n.value));
+        }
+    }
+}
+
+/// Render a recipe as cooklang text.
+///
+/// Metadata come first, as a YAML front matter when some values are not
+/// plain strings, then the sections with their `== name ==` header.
+/// Paragraphs are separated by an empty line, with one step per line. A
+/// recipe without sections gets a paragraph per step. Parsing the output
+/// gives back the same recipe, spans excepted: empty `[--]` comments keep
+/// texts that would read as comments, metadata, notes or headers as they
+/// are.
+pub fn to_cooklang(recipe: &Recipe) -> String {
+    let mut out = String::new();
+    let one_line = |value: &MetadataValue| value.as_str().is_some_and(|v| !v.contains('\n'));
+    if recipe.metadata.values().all(one_line) {
+        for (key, value) in &recipe.metadata {
+            let value = value.to_string();
+            out.push_str(&format!(">> {key}: {}\n", escape_comments(&value)));
+        }
+    } else {
+        out.push_str(&to_front_matter(&recipe.metadata));
+    }
+    let one_per_step;
+    let sections = if recipe.sections.is_empty() && !recipe.steps.is_empty() {
+        one_per_step = [Section {
+            paragraphs: (0..recipe.steps.len()).map(|i| i..i + 1).collect(),
+            ..Default::default()
+        }];
+        &one_per_step[..]
+    } else {
+        &recipe.sections[..]
+    };
+    for (i, section) in sections.iter().enumerate() {
+        let mut after_header = false;
+        // the first section is implicit, unless it has a name or no steps
+        if i > 0 || !section.name.is_empty() || section.paragraphs.is_empty() {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            match section.name.as_str() {
+                "" => out.push_str("==\n"),
+                name => out.push_str(&format!("== {} ==\n", escape_comments(name))),
+            }
+            after_header = true;
+        }
+        for paragraph in &section.paragraphs {
+            if !out.is_empty() && !after_header {
+                out.push('\n');
+            }
+            after_header = false;
+            for step in &recipe.steps[paragraph.clone()] {
+                write_step(&mut out, step);
+                out.push('\n');
+            }
+        }
+    }
+    out
 }
 
 #[cfg(test)]
@@ -272,10 +1314,40 @@ mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
 
+    /// parts of every parsed step, spans excluded
+    fn parts(input: &str) -> Vec<Vec<Part>> {
+        parse(input.to_string())
+            .unwrap()
+            .steps
+            .into_iter()
+            .map(|step| {
+                step.parts
+                    .iter()
+                    .map(|part| {
+                        let mut part = part.as_borrowed();
+                        part.set_span(Span::default());
+                        part.into_owned()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn text_part(value: &str) -> Part {
+        Part::Text(Text {
+            value: value.to_string(),
+            ..Default::default()
+        })
+    }
+
     #[test]
     fn test_block_comment() {
         assert_eq!(block_comment("[- foo bar-]"), Ok(("", "")));
         assert_eq!(block_comment("[-foo-] bar"), Ok((" bar", "")));
+        assert!(matches!(
+            block_comment("[- foo bar"),
+            Err(nom::Err::Failure(e)) if e.input == "[- foo bar"
+        ));
     }
 
     #[test]
@@ -299,16 +1371,33 @@ mod tests {
     fn test_remove_comment() {
         assert_eq!(
             remove_comment(String::from("--foo\n bar")),
-            String::from("\n bar")
+            Ok(String::from("\n bar"))
         );
         assert_eq!(
             remove_comment(String::from("fo--foo\n bar")),
-            String::from("fo\n bar")
+            Ok(String::from("fo\n bar"))
         );
         assert_eq!(
             remove_comment(String::from("fo[-bar-]o")),
-            String::from("foo")
+            Ok(String::from("foo"))
         );
+        assert_eq!(
+            remove_comment(String::from("a-b [- -- c\n-]d -- [- e\nf[--]")),
+            Ok(String::from("a-b d \nf"))
+        );
+        assert_eq!(
+            remove_comment(String::from("crème [-brûlée-]fraîche -- à goûter")),
+            Ok(String::from("crème fraîche "))
+        );
+        let error = remove_comment(String::from("Mix\nthé [- later")).unwrap_err();
+        assert_eq!(
+            (error.expected, error.line, error.column, error.offset),
+            (Expected::ClosingComment, 2, 5, 9)
+        );
+
+        let step = "Stir the crème [- gently -] for ~{2%minutes} -- or more\n";
+        let expected = "Stir the crème  for ~{2%minutes} \n".repeat(50_000);
+        assert_eq!(remove_comment(step.repeat(50_000)), Ok(expected));
     }
 
     #[test]
@@ -317,58 +1406,595 @@ mod tests {
             metadata(">> plop: coucou\nfoo"),
             Ok((
                 "\nfoo",
-                Part::Metadata(Metadata {
+                Metadata {
                     key: "plop".to_string(),
                     value: "coucou".to_string()
-                })
+                }
             ))
         );
         assert_eq!(
             metadata(">> plop: coucou"),
             Ok((
                 "",
-                Part::Metadata(Metadata {
+                Metadata {
                     key: "plop".to_string(),
                     value: "coucou".to_string()
-                })
+                }
             ))
         );
+        // block comments are left out of the value, a line comment ends it
+        let recipe = parse(String::from(
+            ">> source: grandma [- old -] book\n>> by: Jo -- not sure\nMix",
+        ))
+        .unwrap();
+        assert_eq!(recipe.metadata["source"], "grandma  book");
+        assert_eq!(recipe.metadata["by"], "Jo");
+        assert_eq!(recipe.steps.len(), 1);
     }
 
     #[test]
     fn test_text() {
         assert_eq!(
-            text("foo bar"),
-            Ok(("", Part::Text(String::from("foo bar"))))
+            text("foo bar").map(|(rest, part)| (rest, part.into_owned())),
+            Ok(("", text_part("foo bar")))
         );
     }
 
     #[test]
     fn test_parse() {
+        let recipe = parse(String::from(">> plop: coucou")).unwrap();
         assert_eq!(
-            parse(String::from(">> plop: coucou")),
-            vec![vec![Part::Metadata(Metadata {
-                key: "plop".to_string(),
-                value: "coucou".to_string()
-            }),]]
+            recipe.metadata,
+            IndexMap::from([("plop".to_string(), MetadataValue::from("coucou"))])
+        );
+        assert_eq!(recipe.steps, vec![]);
+        assert_eq!(
+            parts(">> plop: coucou\nplop"),
+            vec![vec![text_part("plop")]]
+        );
+    }
+
+    #[test]
+    fn test_recipe_collections() {
+        let recipe = parse(String::from(
+            "Boil @water{1%l} in #pot for ~{10%minutes}\nAdd @salt and @water{500%ml} to the #pot for ~{10%minutes}",
+        ))
+        .unwrap();
+        let ingredients: Vec<(&str, &Quantity)> = recipe
+            .ingredients()
+            .into_iter()
+            .map(|i| (i.name.as_str(), &i.quantity))
+            .collect();
+        assert_eq!(
+            ingredients,
+            vec![("water", &Quantity::Integer(1)), ("salt", &Quantity::Empty)]
         );
+        assert_eq!(recipe.cookware().len(), 1);
+        assert_eq!(recipe.timers().len(), 1);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json() {
+        let recipe = parse(String::from(
+            ">> servings: 2\nFry @onion{1} in the #pan for ~{1/2%hour}",
+        ))
+        .unwrap();
+        let json = serde_json::json!({
+            "metadata": {"servings": "2"},
+            "steps": [
+                [
+                    {"type": "text", "value": "Fry"},
+                    {"type": "ingredient", "name": "onion", "quantity": 1, "units": ""},
+                    {"type": "text", "value": "in the"},
+                    {"type": "cookware", "name": "pan", "quantity": 1},
+                    {"type": "text", "value": "for"},
+                    {"type": "timer", "name": "", "quantity": 0.5, "units": "hour"}
+                ]
+            ],
+            "sections": [{"name": "", "paragraphs": [{"start": 0, "end": 1}]}]
+        });
+        assert_eq!(serde_json::to_value(&recipe).unwrap(), json);
         assert_eq!(
-            parse(String::from(">> plop: coucou\nplop")),
+            serde_json::from_value::<Quantity>(serde_json::json!(1.5)).unwrap(),
+            Quantity::Decimal(1.5)
+        );
+
+        let salt = serde_json::json!({"name": "salt", "quantity": "some", "units": ""});
+        let ingredient: Ingredient = serde_json::from_value(salt.clone()).unwrap();
+        assert_eq!(ingredient.quantity, Quantity::Empty);
+        assert_eq!(serde_json::to_value(&ingredient).unwrap(), salt);
+    }
+
+    #[test]
+    fn test_spans() {
+        let steps = parse(String::from(
+            ">> servings: 2\n-- comment\nMix the 🧂 [- with -] @flour{250%g} in #bowl",
+        ))
+        .unwrap()
+        .steps;
+        let spans: Vec<Span> = steps[0].parts.iter().map(Part::span).collect();
+        assert_eq!(
+            spans,
             vec![
-                vec![Part::Metadata(Metadata {
-                    key: "plop".to_string(),
-                    value: "coucou".to_string()
+                Span {
+                    start: 26,
+                    end: 38,
+                    line: 3,
+                    column: 1
+                },
+                Span {
+                    start: 50,
+                    end: 63,
+                    line: 3,
+                    column: 22
+                },
+                Span {
+                    start: 64,
+                    end: 66,
+                    line: 3,
+                    column: 36
+                },
+                Span {
+                    start: 67,
+                    end: 72,
+                    line: 3,
+                    column: 39
+                },
+            ]
+        );
+        assert_eq!(
+            steps[0].span,
+            Span {
+                start: 26,
+                end: 72,
+                line: 3,
+                column: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_comment_inside_text() {
+        assert_eq!(
+            parts("Mash [-gently-] the potatoes -- or not\n@salt-- to taste"),
+            vec![
+                vec![text_part("Mash  the potatoes")],
+                vec![Part::Ingredient(Ingredient {
+                    name: "salt".to_string(),
+                    ..Default::default()
+                })]
+            ]
+        );
+        let error = parse(String::from(
+            "Keep -- [- in a line comment\nbut not [- here\nor @here",
+        ))
+        .unwrap_err();
+        assert_eq!(
+            (error.expected, error.line, error.column),
+            (Expected::ClosingComment, 2, 9)
+        );
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        assert_eq!(remove_comment(String::new()), Ok(String::new()));
+        assert_eq!(parse(String::new()), Ok(Recipe::default()));
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert_eq!(
+            parse(String::from("Add @flour{250%g")),
+            Err(ParseError {
+                line: 1,
+                column: 11,
+                offset: 10,
+                expected: Expected::ClosingBrace,
+                message: "unclosed `{`".to_string()
+            })
+        );
+        assert_eq!(
+            parse(String::from(">> servings: 2\n>> source")),
+            Err(ParseError {
+                line: 2,
+                column: 1,
+                offset: 15,
+                expected: Expected::MetadataValue,
+                message: "expected `:` after metadata key".to_string()
+            })
+        );
+        assert_eq!(
+            parse(String::from("Wait ~ a bit")).unwrap_err().expected,
+            Expected::Amount
+        );
+        assert_eq!(
+            parse(String::from("Fry for ~{10%minutes"))
+                .unwrap_err()
+                .column,
+            10
+        );
+        assert_eq!(
+            parse(String::from("Add @ to taste")).unwrap_err().expected,
+            Expected::Name
+        );
+        assert_eq!(
+            parse(String::from("Add {2} eggs")).unwrap_err().expected,
+            Expected::Name
+        );
+    }
+
+    #[test]
+    fn test_quantity() {
+        assert_eq!(Quantity::from(""), Quantity::Empty);
+        assert_eq!(Quantity::from("3"), Quantity::Integer(3));
+        assert_eq!(Quantity::from("1.5"), Quantity::Decimal(1.5));
+        assert_eq!(Quantity::from("1/2"), Quantity::Fraction(1, 2));
+        assert_eq!(Quantity::from("1 / 2"), Quantity::Fraction(1, 2));
+        assert_eq!(Quantity::from("01/2"), Quantity::Text("01/2".to_string()));
+        assert_eq!(Quantity::from("1/0"), Quantity::Text("1/0".to_string()));
+        assert_eq!(Quantity::from("7 k"), Quantity::Text("7 k".to_string()));
+        assert_eq!(Quantity::Fraction(1, 2).value(), Some(0.5));
+        assert_eq!(Quantity::Fraction(1, 2).to_string(), "1/2");
+        assert_eq!(Quantity::Text("few".to_string()).value(), None);
+        assert_eq!(Quantity::from("2.0"), Quantity::Decimal(2.0));
+        assert_eq!(Quantity::Decimal(2.0).to_string(), "2.0");
+    }
+
+    #[test]
+    fn test_to_cooklang() {
+        let source = ">>servings:2\nAdd @chilli{ 3 % items }, @salt{= 1%tsp} and @hot sauce{}.\n\n\
+            Fry in #frying pan{ } for ~{1 / 2%hour} and ~rest{2.0%min} -- comment";
+        let expected = ">> servings: 2\n\n\
+            Add @chilli{3%items}, @salt{=1%tsp} and @hot sauce{}.\n\n\
+            Fry in #frying pan{} for ~{1/2%hour} and ~rest{2.0%min}\n";
+        let written = to_cooklang(&parse(source.to_string()).unwrap());
+        assert_eq!(written, expected);
+        assert_eq!(parts(&written), parts(source));
+    }
+
+    #[test]
+    fn test_sections() {
+        let source = "Preheat the #oven\n\
+            \n\
+            == Dough ==\n\
+            Mix @flour{500%g}\n\
+            -- comment\n\
+            and @water{300%ml}\n\
+            \n\
+            Knead\n\
+            = Assembly\n\
+            == Empty ==";
+        let recipe = parse(source.to_string()).unwrap();
+        let outline = |recipe: &Recipe| -> Vec<(String, Vec<Range<usize>>)> {
+            recipe
+                .sections
+                .iter()
+                .map(|s| (s.name.clone(), s.paragraphs.clone()))
+                .collect()
+        };
+        assert_eq!(
+            outline(&recipe),
+            vec![
+                ("".to_string(), vec![Range { start: 0, end: 1 }]),
+                ("Dough".to_string(), vec![1..3, 3..4]),
+                ("Assembly".to_string(), vec![]),
+                ("Empty".to_string(), vec![]),
+            ]
+        );
+        assert_eq!(recipe.sections[1].steps(&recipe).len(), 3);
+        assert_eq!(recipe.sections[1].span.line, 3);
+
+        let written = to_cooklang(&recipe);
+        assert_eq!(
+            written,
+            "Preheat the #oven\n\
+            \n\
+            == Dough ==\n\
+            Mix @flour{500%g}\n\
+            and @water{300%ml}\n\
+            \n\
+            Knead\n\
+            \n\
+            == Assembly ==\n\
+            \n\
+            == Empty ==\n"
+        );
+        assert_eq!(outline(&parse(written).unwrap()), outline(&recipe));
+
+        // block comments are cut out of the header, which covers the line
+        let recipe = parse(String::from("== Dough [- x -] part ==\nMix")).unwrap();
+        assert_eq!(recipe.sections[0].name, "Dough  part");
+        assert_eq!(recipe.steps.len(), 1);
+    }
+
+    #[test]
+    fn test_note() {
+        let source = ">> servings: 2\n> Don't burn the @roux! -- comment\nStir the @roux{1%cup}\n>> other: 3";
+        let recipe = parse(source.to_string()).unwrap();
+        assert_eq!(
+            parts(source),
+            vec![
+                vec![Part::Note(Note {
+                    value: "Don't burn the @roux!".to_string(),
+                    ..Default::default()
+                })],
+                vec![
+                    text_part("Stir the"),
+                    Part::Ingredient(Ingredient {
+                        name: "roux".to_string(),
+                        quantity: Quantity::Integer(1),
+                        units: "cup".to_string(),
+                        ..Default::default()
+                    }),
+                ],
+            ]
+        );
+        assert!(recipe.steps[0].is_note());
+        assert!(!recipe.steps[1].is_note());
+        assert_eq!(recipe.steps[0].span.line, 2);
+        assert_eq!(recipe.ingredients().len(), 1);
+        assert_eq!(recipe.metadata.len(), 2);
+
+        let written = to_cooklang(&recipe);
+        assert!(written.contains("\n> Don't burn the @roux!\n"));
+        assert_eq!(parts(&written), parts(source));
+
+        // block comments are cut out of the note, which covers the line
+        assert_eq!(
+            parts("> Serve hot [- really -] with bread\nEat"),
+            vec![
+                vec![Part::Note(Note {
+                    value: "Serve hot  with bread".to_string(),
+                    ..Default::default()
                 })],
-                vec![Part::Text(String::from("plop"))]
+                vec![text_part("Eat")],
             ]
         );
     }
 
+    #[test]
+    fn test_front_matter() {
+        let source = "---\n\
+            title: Bread\n\
+            servings: 4\n\
+            tags: [baking]\n\
+            ---\n\
+            >> servings: 6\n\
+            >> title: Bread\n\
+            >> course: dinner\n\
+            Knead @flour{500%g}";
+        let recipe = parse(source.to_string()).unwrap();
+        assert_eq!(
+            recipe.metadata,
+            IndexMap::from([
+                ("title".to_string(), MetadataValue::from("Bread")),
+                ("servings".to_string(), MetadataValue::Number(4.0)),
+                (
+                    "tags".to_string(),
+                    MetadataValue::List(vec!["baking".into()])
+                ),
+                ("course".to_string(), MetadataValue::from("dinner")),
+            ])
+        );
+        assert_eq!(
+            recipe.warnings,
+            vec![Warning {
+                span: Span {
+                    start: 48,
+                    end: 62,
+                    line: 6,
+                    column: 1
+                },
+                message: "`servings` is already `4` in the front matter, `6` is ignored"
+                    .to_string()
+            }]
+        );
+        assert_eq!(recipe.steps[0].span.line, 9);
+        assert_eq!(recipe.steps[0].parts[1].span().start, 103);
+
+        let written = to_cooklang(&recipe);
+        assert!(written.starts_with("---\ntitle: Bread\nservings: 4\n"));
+        assert_eq!(parse(written).unwrap().metadata, recipe.metadata);
+
+        let error = parse("---\ntitle: [Bread\n---\nKnead".to_string()).unwrap_err();
+        assert_eq!(error.expected, Expected::FrontMatter);
+        assert_eq!(error.line, 3);
+        // without its closing line, `---` is a comment
+        let source = "---\ntitle: Bread\nKnead";
+        assert!(parse(source.to_string()).unwrap().metadata.is_empty());
+        assert_eq!(
+            parts(source),
+            vec![vec![text_part("title: Bread")], vec![text_part("Knead")]]
+        );
+    }
+
+    #[test]
+    fn test_parse_recovering() {
+        let source = "---\ntitle: [Bread\n---\n>> servings: 4\nMix @flour{500%g} and @{1}\n\
+            Add @&water{}, @&salt{} and @salt{1%tsp}\nKnead {2} then #bowl{\nBake.";
+        let (recipe, diagnostics) = parse_recovering(source);
+        let messages: Vec<_> = diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "3:1: error: invalid front matter: did not find expected ',' or ']' at line 2 column 1, while parsing a flow sequence at line 1 column 8",
+                "5:23: error: expected a name after `@`",
+                "6:5: error: `water` is referenced before its definition",
+                "6:16: error: `salt` is referenced before its definition",
+                "7:7: error: amount is not attached to an ingredient, cookware or timer",
+            ]
+        );
+        assert_eq!(recipe.metadata["servings"], MetadataValue::from("4"));
+        let steps: Vec<_> = recipe.steps.iter().map(|s| s.span.line).collect();
+        assert_eq!(steps, vec![6, 8]);
+        let names: Vec<_> = recipe
+            .ingredients()
+            .iter()
+            .map(|i| i.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["water", "salt"]);
+
+        // an unclosed comment runs to the end of the recipe
+        let (recipe, diagnostics) =
+            parse_recovering("Boil @water\nMix @flour [- later\nStir [- again");
+        let messages: Vec<_> = diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(messages, vec!["2:12: error: unclosed `[-` comment"]);
+        assert_eq!(recipe.steps.len(), 1);
+        let (tree, _) = crate::cst::SyntaxTree::parse_recovering("Mix [- later\nStir");
+        assert_eq!(tree.tokens.last().unwrap().text, "Mix [- later\nStir");
+
+        let (recipe, diagnostics) = parse_recovering("Mix @flour{1%kg}");
+        assert_eq!(diagnostics, vec![]);
+        assert_eq!(recipe, parse("Mix @flour{1%kg}".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_ingredient_note() {
+        let onion = |quantity: Quantity, note: &str| {
+            Part::Ingredient(Ingredient {
+                name: "onion".to_string(),
+                quantity,
+                note: note.to_string(),
+                ..Default::default()
+            })
+        };
+        assert_eq!(
+            parts("Fry @onion{1}( finely chopped ) and @onion{}(sliced), @onion (whole)"),
+            vec![vec![
+                text_part("Fry"),
+                onion(Quantity::Integer(1), "finely chopped"),
+                text_part("and"),
+                onion(Quantity::Empty, "sliced"),
+                text_part(","),
+                onion(Quantity::Empty, ""),
+                text_part("(whole)"),
+            ]]
+        );
+        assert_eq!(
+            parts("Add @onion{1}(unclosed"),
+            vec![vec![
+                text_part("Add"),
+                onion(Quantity::Integer(1), ""),
+                text_part("(unclosed"),
+            ]]
+        );
+        let source = "Fry @onion{}(sliced) and @red onion{1}(diced)";
+        let written = to_cooklang(&parse(source.to_string()).unwrap());
+        assert_eq!(written, "Fry @onion{}(sliced) and @red onion{1}(diced)\n");
+    }
+
+    #[test]
+    fn test_modifiers() {
+        let ingredient = |name: &str, quantity: Quantity| Ingredient {
+            name: name.to_string(),
+            quantity,
+            ..Default::default()
+        };
+        let source =
+            "Mix @?parsley, @-salt and @flour{500%g}, then @&flour{100%g} and @./sauces/Pesto{1}";
+        assert_eq!(
+            parts(source),
+            vec![vec![
+                text_part("Mix"),
+                Part::Ingredient(Ingredient {
+                    optional: true,
+                    ..ingredient("parsley", Quantity::Empty)
+                }),
+                text_part(","),
+                Part::Ingredient(Ingredient {
+                    hidden: true,
+                    ..ingredient("salt", Quantity::Empty)
+                }),
+                text_part("and"),
+                Part::Ingredient(Ingredient {
+                    units: "g".to_string(),
+                    ..ingredient("flour", Quantity::Integer(500))
+                }),
+                text_part(", then"),
+                Part::Ingredient(Ingredient {
+                    units: "g".to_string(),
+                    reference: true,
+                    ..ingredient("flour", Quantity::Integer(100))
+                }),
+                text_part("and"),
+                Part::Ingredient(Ingredient {
+                    recipe_link: true,
+                    ..ingredient("./sauces/Pesto", Quantity::Integer(1))
+                }),
+            ]]
+        );
+        let recipe = parse(source.to_string()).unwrap();
+        let names: Vec<&str> = recipe
+            .ingredients()
+            .iter()
+            .map(|i| i.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["parsley", "flour", "./sauces/Pesto"]);
+
+        let written = to_cooklang(&recipe);
+        assert_eq!(
+            written,
+            "Mix @?parsley, @-salt and @flour{500%g}, then @&flour{100%g} and @./sauces/Pesto{1}\n"
+        );
+        assert_eq!(parts(&written), parts(source));
+        assert_eq!(parts("@salt @?-&salt"), parts("@salt @&-?salt"));
+
+        // without braces, the path ends at the first space
+        assert_eq!(
+            parts("Use @./sauces/Pesto and stir in @salt{1%g}."),
+            vec![vec![
+                text_part("Use"),
+                Part::Ingredient(Ingredient {
+                    recipe_link: true,
+                    ..ingredient("./sauces/Pesto", Quantity::Empty)
+                }),
+                text_part("and stir in"),
+                Part::Ingredient(Ingredient {
+                    units: "g".to_string(),
+                    ..ingredient("salt", Quantity::Integer(1))
+                }),
+                text_part("."),
+            ]]
+        );
+        assert_eq!(
+            parts("Add @../Pizza Dough{2} and #pan{}"),
+            vec![vec![
+                text_part("Add"),
+                Part::Ingredient(Ingredient {
+                    recipe_link: true,
+                    ..ingredient("../Pizza Dough", Quantity::Integer(2))
+                }),
+                text_part("and"),
+                Part::Cookware(Cookware {
+                    name: "pan".to_string(),
+                    quantity: Quantity::Empty,
+                    ..Default::default()
+                }),
+            ]]
+        );
+
+        assert_eq!(
+            parse("Add @&flour{100%g}\nthen @flour".to_string()),
+            Err(ParseError {
+                line: 1,
+                column: 5,
+                offset: 4,
+                expected: Expected::Definition,
+                message: "`flour` is referenced before its definition".to_string()
+            })
+        );
+        assert_eq!(
+            parse("Add @??salt".to_string()).unwrap_err().expected,
+            Expected::Name
+        );
+    }
+
     #[test]
     fn test_space() {
-        assert_eq!(space("   "), Ok(("", "   ".to_string())));
-        assert_eq!(space("   aa"), Ok(("aa", "   ".to_string())));
-        assert_eq!(space("   \taa"), Ok(("aa", "   \t".to_string())));
+        assert_eq!(space("   "), Ok(("", "   ")));
+        assert_eq!(space("   aa"), Ok(("aa", "   ")));
+        assert_eq!(space("   \taa"), Ok(("aa", "   \t")));
     }
 
     #[test]
@@ -378,7 +2004,7 @@ mod tests {
 
     #[test]
     fn test_word() {
-        assert_eq!(word("bar\nfoo"), Ok(("\nfoo", "bar".to_string())));
+        assert_eq!(word("bar\nfoo"), Ok(("\nfoo", "bar")));
     }
 
     /// tests from https://github.com/cooklang/spec/blob/main/tests/canonical.yaml
@@ -386,32 +2012,28 @@ mod tests {
     #[test]
     fn test_basic_direction() {
         assert_eq!(
-            parse(String::from("Add a bit of chilli")),
-            vec![vec![Part::Text(String::from("Add a bit of chilli"))]]
+            parts("Add a bit of chilli"),
+            vec![vec![text_part("Add a bit of chilli")]]
         );
     }
 
     #[test]
     fn test_comments() {
-        assert_eq!(
-            parse(String::from("-- testing comments")),
-            Vec::<Vec<Part>>::new()
-        );
+        assert_eq!(parts("-- testing comments"), Vec::<Vec<Part>>::new());
     }
 
     #[test]
     fn test_comments_after_ingredients() {
         assert_eq!(
-            parse(String::from(
-                "@thyme{2%springs} -- testing comments\n  and some text"
-            )),
+            parts("@thyme{2%springs} -- testing comments\n  and some text"),
             vec![
                 vec![Part::Ingredient(Ingredient {
                     name: "thyme".to_string(),
-                    quantity: "2".to_string(),
-                    units: "springs".to_string()
+                    quantity: Quantity::Integer(2),
+                    units: "springs".to_string(),
+                    ..Default::default()
                 })],
-                vec![Part::Text("and some text".to_string())]
+                vec![text_part("and some text")]
             ]
         );
     }
@@ -419,13 +2041,12 @@ mod tests {
     #[test]
     fn test_comments_with_ingredients() {
         assert_eq!(
-            parse(String::from(
-                "-- testing comments\n        @thyme{2%springs}"
-            )),
+            parts("-- testing comments\n        @thyme{2%springs}"),
             vec![vec![Part::Ingredient(Ingredient {
                 name: "thyme".to_string(),
-                quantity: "2".to_string(),
-                units: "springs".to_string()
+                quantity: Quantity::Integer(2),
+                units: "springs".to_string(),
+                ..Default::default()
             }),]]
         );
     }
@@ -433,29 +2054,30 @@ mod tests {
     #[test]
     fn test_direction_with_ingrident() {
         assert_eq!(
-            parse(String::from(
-                "Add @chilli{3%items}, @ginger{10%g} and @milk{1%l}."
-            )),
+            parts("Add @chilli{3%items}, @ginger{10%g} and @milk{1%l}."),
             vec![vec![
-                Part::Text("Add".to_string()),
+                text_part("Add"),
                 Part::Ingredient(Ingredient {
                     name: "chilli".to_string(),
-                    quantity: "3".to_string(),
-                    units: "items".to_string()
+                    quantity: Quantity::Integer(3),
+                    units: "items".to_string(),
+                    ..Default::default()
                 }),
-                Part::Text(",".to_string()),
+                text_part(","),
                 Part::Ingredient(Ingredient {
                     name: "ginger".to_string(),
-                    quantity: "10".to_string(),
-                    units: "g".to_string()
+                    quantity: Quantity::Integer(10),
+                    units: "g".to_string(),
+                    ..Default::default()
                 }),
-                Part::Text("and".to_string()),
+                text_part("and"),
                 Part::Ingredient(Ingredient {
                     name: "milk".to_string(),
-                    quantity: "1".to_string(),
-                    units: "l".to_string()
+                    quantity: Quantity::Integer(1),
+                    units: "l".to_string(),
+                    ..Default::default()
                 }),
-                Part::Text(".".to_string())
+                text_part(".")
             ]]
         )
     }
@@ -463,9 +2085,9 @@ mod tests {
     #[test]
     fn test_equipment_multiple_words() {
         assert_eq!(
-            parse(String::from("Fry in #frying pan{}")),
+            parts("Fry in #frying pan{}"),
             vec![vec![
-                Part::Text("Fry in".to_string()),
+                text_part("Fry in"),
                 Part::Cookware(Cookware {
                     name: "frying pan".to_string(),
                     ..Default::default()
@@ -477,9 +2099,9 @@ mod tests {
     #[test]
     fn test_equipment_multiple_words_with_leading_number() {
         assert_eq!(
-            parse(String::from("Fry in #7-inch nonstick frying pan{ }")),
+            parts("Fry in #7-inch nonstick frying pan{ }"),
             vec![vec![
-                Part::Text("Fry in".to_string()),
+                text_part("Fry in"),
                 Part::Cookware(Cookware {
                     name: "7-inch nonstick frying pan".to_string(),
                     ..Default::default()
@@ -491,9 +2113,9 @@ mod tests {
     #[test]
     fn test_equipment_multiple_words_with_spaces() {
         assert_eq!(
-            parse(String::from("Fry in #frying pan{ }")),
+            parts("Fry in #frying pan{ }"),
             vec![vec![
-                Part::Text("Fry in".to_string()),
+                text_part("Fry in"),
                 Part::Cookware(Cookware {
                     name: "frying pan".to_string(),
                     ..Default::default()
@@ -505,14 +2127,14 @@ mod tests {
     #[test]
     fn test_equipment_one_word() {
         assert_eq!(
-            parse(String::from("Simmer in #pan for some time")),
+            parts("Simmer in #pan for some time"),
             vec![vec![
-                Part::Text("Simmer in".to_string()),
+                text_part("Simmer in"),
                 Part::Cookware(Cookware {
                     name: "pan".to_string(),
                     ..Default::default()
                 }),
-                Part::Text("for some time".to_string())
+                text_part("for some time")
             ]]
         )
     }
@@ -520,13 +2142,14 @@ mod tests {
     #[test]
     fn test_ingredient_with_emoji() {
         assert_eq!(
-            parse(String::from("Add some @🧂")),
+            parts("Add some @🧂"),
             vec![vec![
-                Part::Text("Add some".to_string()),
+                text_part("Add some"),
                 Part::Ingredient(Ingredient {
                     name: "🧂".to_string(),
-                    quantity: "".to_string(),
-                    units: "".to_string()
+                    quantity: Quantity::Empty,
+                    units: "".to_string(),
+                    ..Default::default()
                 })
             ]]
         )
@@ -535,11 +2158,12 @@ mod tests {
     #[test]
     fn test_ingrident_explicit_units() {
         assert_eq!(
-            parse(String::from("@chilli{3%items}")),
+            parts("@chilli{3%items}"),
             vec![vec![Part::Ingredient(Ingredient {
                 name: "chilli".to_string(),
-                quantity: "3".to_string(),
-                units: "items".to_string()
+                quantity: Quantity::Integer(3),
+                units: "items".to_string(),
+                ..Default::default()
             })]]
         )
     }
@@ -547,20 +2171,19 @@ mod tests {
     #[test]
     fn test_ingrident_explicit_units_with_spaces() {
         assert_eq!(
-            parse(String::from("@chilli{ 3 % items }")),
+            parts("@chilli{ 3 % items }"),
             vec![vec![Part::Ingredient(Ingredient {
                 name: "chilli".to_string(),
-                quantity: "3".to_string(),
-                units: "items".to_string()
+                quantity: Quantity::Integer(3),
+                units: "items".to_string(),
+                ..Default::default()
             })]]
         )
     }
 
     #[test]
     fn test_full() {
-        assert_eq!(
-            parse(String::from(
-                "
+        let source = "
 >> source: https://www.gimmesomeoven.com/baked-potato/
 >> time required: 1.5 hours
 >> course: dinner
@@ -568,38 +2191,57 @@ mod tests {
 
 Mash @potato{2%kg} until smooth -- alternatively, boil 'em first, then mash 'em, then stick 'em in a stew.
 Place @bacon strips{1%kg} on a baking sheet and glaze with @syrup{1/2%tbsp}.
-"
-            )),
+";
+        assert_eq!(
+            parse(source.to_string()).unwrap().metadata,
+            IndexMap::from([
+                (
+                    "source".to_string(),
+                    MetadataValue::from("https://www.gimmesomeoven.com/baked-potato/")
+                ),
+                (
+                    "time required".to_string(),
+                    MetadataValue::from("1.5 hours")
+                ),
+                ("course".to_string(), MetadataValue::from("dinner")),
+            ])
+        );
+        assert_eq!(
+            parse(source.to_string())
+                .unwrap()
+                .known_metadata()
+                .time_required(),
+            Ok(Some(std::time::Duration::from_secs(5400)))
+        );
+        assert_eq!(
+            parts(source),
             vec![
-                vec![Part::Metadata(Metadata {
-                    key: "source".to_string(),
-                    value: "https://www.gimmesomeoven.com/baked-potato/".to_string()
-                })],
-                vec![Part::Metadata(Metadata { key: "time required".to_string(), value: "1.5 hours".to_string() })],
-                vec![Part::Metadata(Metadata { key: "course".to_string(), value: "dinner".to_string() })],
                 vec![
-                    Part::Text("Mash".to_string()),
+                    text_part("Mash"),
                     Part::Ingredient(Ingredient {
                         name: "potato".to_string(),
-                        quantity: "2".to_string(),
-                        units: "kg".to_string()
+                        quantity: Quantity::Integer(2),
+                        units: "kg".to_string(),
+                        ..Default::default()
                     }),
-                    Part::Text("until smooth".to_string())
+                    text_part("until smooth")
                 ],
                 vec![
-                    Part::Text("Place".to_string()),
+                    text_part("Place"),
                     Part::Ingredient(Ingredient {
                         name: "bacon strips".to_string(),
-                        quantity: "1".to_string(),
-                        units: "kg".to_string()
+                        quantity: Quantity::Integer(1),
+                        units: "kg".to_string(),
+                        ..Default::default()
                     }),
-                    Part::Text("on a baking sheet and glaze with".to_string()),
+                    text_part("on a baking sheet and glaze with"),
                     Part::Ingredient(Ingredient {
                         name: "syrup".to_string(),
-                        quantity: "1/2".to_string(),
-                        units: "tbsp".to_string()
+                        quantity: Quantity::Fraction(1, 2),
+                        units: "tbsp".to_string(),
+                        ..Default::default()
                     }),
-                    Part::Text(".".to_string())
+                    text_part(".")
                 ],
             ]
         )
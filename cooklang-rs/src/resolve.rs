@@ -0,0 +1,416 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use indexmap::IndexMap;
+
+use crate::parser::{parse, Ingredient, ParseError, Part, Recipe};
+use crate::shopping::{ShoppingItem, ShoppingList};
+
+/// extension added to recipe links written without one
+const EXTENSION: &str = "cook";
+
+/// Source of the text of recipes
+pub trait Loader {
+    fn load(&self, path: &Path) -> io::Result<String>;
+}
+
+/// Recipes read from the filesystem
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FileLoader;
+
+impl Loader for FileLoader {
+    fn load(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+}
+
+/// Recipes kept in memory, by path
+#[derive(Debug, Default, Clone)]
+pub struct MemoryLoader {
+    recipes: HashMap<PathBuf, String>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, path: impl AsRef<Path>, text: impl Into<String>) {
+        self.recipes.insert(normalize(path.as_ref()), text.into());
+    }
+}
+
+impl Loader for MemoryLoader {
+    fn load(&self, path: &Path) -> io::Result<String> {
+        self.recipes.get(&normalize(path)).cloned().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no recipe at {}", path.display()),
+            )
+        })
+    }
+}
+
+/// Error returned when a recipe or one of its links can not be resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResolveError {
+    /// the recipe could not be loaded
+    Load(PathBuf, String),
+    /// the recipe is not valid
+    Parse(PathBuf, ParseError),
+    /// the recipes link to each other, from the first one back to it
+    Cycle(Vec<PathBuf>),
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::Load(path, e) => write!(f, "{}: {e}", path.display()),
+            ResolveError::Parse(path, e) => write!(f, "{}:{e}", path.display()),
+            ResolveError::Cycle(paths) => {
+                let paths: Vec<_> = paths.iter().map(|p| p.display().to_string()).collect();
+                write!(f, "recipes link to each other: {}", paths.join(" -> "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+/// `path` without `.` and `..` components, so that a recipe has one path
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if normalized.file_name().is_some() => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// path of the recipe linked as `link` from the recipe at `from`
+pub fn link_path(from: &Path, link: &str) -> PathBuf {
+    let mut path = from.parent().unwrap_or(Path::new("")).join(link);
+    // a dot in the name does not start an extension
+    if path.extension().is_none_or(|e| e != EXTENSION) {
+        path.as_mut_os_string().push(format!(".{EXTENSION}"));
+    }
+    normalize(&path)
+}
+
+/// A recipe with the recipes it links to, themselves resolved
+#[derive(Debug, PartialEq)]
+pub struct ResolvedRecipe {
+    pub path: PathBuf,
+    pub recipe: Recipe,
+    /// linked recipes by the name of their `@./path{}` ingredient, shared by
+    /// the recipes linking to the same path
+    pub links: IndexMap<String, Arc<ResolvedRecipe>>,
+}
+
+impl ResolvedRecipe {
+    /// Ingredients of the recipe with the links replaced by the ingredients
+    /// of the linked recipes, recursively.
+    ///
+    /// A link with a quantity and no units, like `@./Pizza Dough{2}`, uses
+    /// that many times the linked recipe, once otherwise. References and hidden
+    /// ingredients are kept, with their modifiers.
+    pub fn flat_ingredients(&self) -> Vec<Ingredient> {
+        let mut ingredients = Vec::new();
+        self.flatten_into(1.0, &mut ingredients);
+        ingredients
+    }
+
+    fn flatten_into(&self, factor: f64, ingredients: &mut Vec<Ingredient>) {
+        let parts = self.recipe.steps.iter().flat_map(|s| s.parts.iter());
+        for part in parts {
+            let Part::Ingredient(i) = part else {
+                continue;
+            };
+            match self.links.get(&i.name) {
+                Some(linked) if i.recipe_link => {
+                    let times = match (&i.quantity, i.units.is_empty()) {
+                        (q, true) => q.value().unwrap_or(1.0),
+                        _ => 1.0,
+                    };
+                    linked.flatten_into(factor * times, ingredients);
+                }
+                _ => ingredients.push(Ingredient {
+                    name: i.name.clone(),
+                    quantity: if i.fixed {
+                        i.quantity.clone()
+                    } else {
+                        i.quantity.scaled(factor)
+                    },
+                    units: i.units.clone(),
+                    fixed: i.fixed,
+                    optional: i.optional,
+                    hidden: i.hidden,
+                    reference: i.reference,
+                    note: i.note.clone(),
+                    ..Default::default()
+                }),
+            }
+        }
+    }
+
+    /// Shopping list of the recipe and the recipes it links to, hidden
+    /// ingredients excepted as in [`ShoppingList::add_recipe`]
+    pub fn shopping_list(&self) -> Vec<ShoppingItem> {
+        let mut list = ShoppingList::new();
+        for ingredient in self.flat_ingredients().iter().filter(|i| !i.hidden) {
+            list.add_ingredient(ingredient);
+        }
+        list.items()
+    }
+}
+
+/// State of one call to [`Resolver::resolve`]
+#[derive(Default)]
+struct Visit {
+    /// the recipes being resolved, to find cycles
+    stack: Vec<PathBuf>,
+    /// the recipes already resolved, by normalized path
+    resolved: HashMap<PathBuf, Arc<ResolvedRecipe>>,
+}
+
+/// Loads recipes and the recipes they link to
+#[derive(Debug, Default)]
+pub struct Resolver<L> {
+    loader: L,
+}
+
+impl<L: Loader> Resolver<L> {
+    pub fn new(loader: L) -> Self {
+        Resolver { loader }
+    }
+
+    /// Load and parse the recipe at `path`, then its links relative to it.
+    /// Each recipe is loaded once, however many recipes link to it.
+    pub fn resolve(&self, path: impl AsRef<Path>) -> Result<ResolvedRecipe, ResolveError> {
+        let path = normalize(path.as_ref());
+        let recipe = self.load(&path)?;
+        self.resolve_links(path, recipe, &mut Visit::default())
+    }
+
+    /// Resolve the links of an already parsed `recipe`, found at `path`
+    pub fn resolve_recipe(
+        &self,
+        path: impl AsRef<Path>,
+        recipe: Recipe,
+    ) -> Result<ResolvedRecipe, ResolveError> {
+        self.resolve_links(normalize(path.as_ref()), recipe, &mut Visit::default())
+    }
+
+    fn load(&self, path: &Path) -> Result<Recipe, ResolveError> {
+        let text = self
+            .loader
+            .load(path)
+            .map_err(|e| ResolveError::Load(path.to_path_buf(), e.to_string()))?;
+        parse(text).map_err(|e| ResolveError::Parse(path.to_path_buf(), e))
+    }
+
+    /// the linked recipe at `path`, resolved once per visit
+    fn resolve_path(
+        &self,
+        path: PathBuf,
+        visit: &mut Visit,
+    ) -> Result<Arc<ResolvedRecipe>, ResolveError> {
+        if let Some(resolved) = visit.resolved.get(&path) {
+            return Ok(Arc::clone(resolved));
+        }
+        if let Some(start) = visit.stack.iter().position(|p| *p == path) {
+            let mut cycle = visit.stack[start..].to_vec();
+            cycle.push(path);
+            return Err(ResolveError::Cycle(cycle));
+        }
+        let recipe = self.load(&path)?;
+        let resolved = Arc::new(self.resolve_links(path.clone(), recipe, visit)?);
+        visit.resolved.insert(path, Arc::clone(&resolved));
+        Ok(resolved)
+    }
+
+    fn resolve_links(
+        &self,
+        path: PathBuf,
+        recipe: Recipe,
+        visit: &mut Visit,
+    ) -> Result<ResolvedRecipe, ResolveError> {
+        visit.stack.push(path.clone());
+        let mut links = IndexMap::new();
+        for part in recipe.steps.iter().flat_map(|s| s.parts.iter()) {
+            match part {
+                Part::Ingredient(i) if i.recipe_link && !links.contains_key(&i.name) => {
+                    let linked = self.resolve_path(link_path(&path, &i.name), visit)?;
+                    links.insert(i.name.clone(), linked);
+                }
+                _ => {}
+            }
+        }
+        visit.stack.pop();
+        Ok(ResolvedRecipe {
+            path,
+            recipe,
+            links,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Quantity;
+    use pretty_assertions::assert_eq;
+
+    fn item(name: &str, quantity: Quantity, units: &str) -> ShoppingItem {
+        ShoppingItem {
+            name: name.to_string(),
+            quantity,
+            units: units.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_link_path() {
+        let from = Path::new("recipes/Pizza.cook");
+        assert_eq!(
+            link_path(from, "./Pizza Dough"),
+            PathBuf::from("recipes/Pizza Dough.cook")
+        );
+        assert_eq!(
+            link_path(from, "../sauces/./Tomato.cook"),
+            PathBuf::from("sauces/Tomato.cook")
+        );
+        assert_eq!(
+            link_path(from, "./Mr. Smith's Sauce"),
+            PathBuf::from("recipes/Mr. Smith's Sauce.cook")
+        );
+        assert_eq!(
+            link_path(from, "./v1.2 dough"),
+            PathBuf::from("recipes/v1.2 dough.cook")
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut loader = MemoryLoader::new();
+        loader.insert(
+            "recipes/Pizza.cook",
+            "Spread @../sauces/Tomato{} on @./Pizza Dough{2}, add @mozzarella{125%g}",
+        );
+        loader.insert(
+            "recipes/Pizza Dough.cook",
+            "Mix @flour{250%g}, @water{150%ml}, @-oil and @salt{=1%tsp}, keep @&flour{50%g} aside",
+        );
+        loader.insert(
+            "sauces/Tomato.cook",
+            "Cook @tomatoes{400%g} with @salt{1%tsp}",
+        );
+        let pizza = Resolver::new(loader)
+            .resolve("recipes/./Pizza.cook")
+            .unwrap();
+        assert_eq!(pizza.path, PathBuf::from("recipes/Pizza.cook"));
+        let links: Vec<_> = pizza.links.values().map(|l| l.path.clone()).collect();
+        assert_eq!(
+            links,
+            vec![
+                PathBuf::from("sauces/Tomato.cook"),
+                PathBuf::from("recipes/Pizza Dough.cook")
+            ]
+        );
+        assert_eq!(
+            pizza.shopping_list(),
+            vec![
+                item("flour", Quantity::Integer(600), "g"),
+                item("mozzarella", Quantity::Integer(125), "g"),
+                item("salt", Quantity::Integer(2), "tsp"),
+                item("tomatoes", Quantity::Integer(400), "g"),
+                item("water", Quantity::Integer(300), "ml"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_errors() {
+        let mut loader = MemoryLoader::new();
+        loader.insert("a.cook", "Use @./b{}");
+        loader.insert("b.cook", "Use @./c{}");
+        loader.insert("c.cook", "Use @./a{}");
+        loader.insert("bad.cook", "Use @./missing{} and @./broken{}");
+        loader.insert("broken.cook", "Add @{1}");
+        let resolver = Resolver::new(loader);
+        assert_eq!(
+            resolver.resolve("b.cook"),
+            Err(ResolveError::Cycle(
+                ["b.cook", "c.cook", "a.cook", "b.cook"]
+                    .map(PathBuf::from)
+                    .to_vec()
+            ))
+        );
+        assert!(matches!(
+            resolver.resolve("bad.cook"),
+            Err(ResolveError::Load(path, _)) if path == Path::new("missing.cook")
+        ));
+        assert!(matches!(
+            resolver.resolve("broken.cook"),
+            Err(ResolveError::Parse(path, _)) if path == Path::new("broken.cook")
+        ));
+    }
+
+    /// a memory loader counting its loads
+    struct Counting(MemoryLoader, std::cell::Cell<usize>);
+
+    impl Loader for Counting {
+        fn load(&self, path: &Path) -> io::Result<String> {
+            self.1.set(self.1.get() + 1);
+            self.0.load(path)
+        }
+    }
+
+    #[test]
+    fn test_resolve_diamonds() {
+        // each level links twice to the next one, through `a` and `b`
+        let levels = 30;
+        let mut loader = MemoryLoader::new();
+        for level in 0..levels {
+            let next = level + 1;
+            loader.insert(
+                format!("{level}.cook"),
+                format!("Use @./a{level}{{}} and @./b{level}{{}}"),
+            );
+            loader.insert(format!("a{level}.cook"), format!("Use @./{next}{{}}"));
+            loader.insert(format!("b{level}.cook"), format!("Use @./{next}{{}}"));
+        }
+        loader.insert(format!("{levels}.cook"), "Add @salt{1%g}");
+        let resolver = Resolver::new(Counting(loader, Default::default()));
+        let resolved = resolver.resolve("0.cook").unwrap();
+        assert_eq!(resolver.loader.1.get(), 3 * levels + 1);
+        let (a, b) = (&resolved.links["./a0"], &resolved.links["./b0"]);
+        assert!(Arc::ptr_eq(&a.links["./1"], &b.links["./1"]));
+    }
+
+    #[test]
+    fn test_file_loader() {
+        let dir = std::env::temp_dir().join(format!("cooklang-resolve-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("Pesto.cook"), "Blend @basil{50%g}").unwrap();
+        fs::write(dir.join("Pasta.cook"), "Mix @pasta{500%g} with @./Pesto{}").unwrap();
+        let pasta = Resolver::new(FileLoader).resolve(dir.join("Pasta.cook"));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            pasta.unwrap().shopping_list(),
+            vec![
+                item("basil", Quantity::Integer(50), "g"),
+                item("pasta", Quantity::Integer(500), "g"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use std::fmt;
+
+use crate::metadata::{KnownMetadata, MetadataError, MetadataValue, SERVINGS_KEYS};
+use crate::parser::{Part, Quantity, Recipe};
+
+/// Error returned when a recipe can not be scaled to a number of servings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScaleError {
+    /// the recipe has no `servings` metadata
+    MissingServings,
+    /// the `servings` metadata can not be read
+    InvalidServings(MetadataError),
+}
+
+impl fmt::Display for ScaleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScaleError::MissingServings => write!(f, "the recipe has no servings metadata"),
+            ScaleError::InvalidServings(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ScaleError {}
+
+/// a scaled fraction keeps at most this many times its denominator
+const MAX_DENOMINATOR_FACTOR: u64 = 10;
+
+/// `value` as a whole number, if it is one
+fn whole(value: f64) -> Option<u64> {
+    let rounded = value.round();
+    // `as` would saturate the values too large for a u64
+    let fits = (0.0..u64::MAX as f64).contains(&rounded);
+    ((value - rounded).abs() < 1e-9 && fits).then_some(rounded as u64)
+}
+
+/// decimals are kept to the thousandth
+pub(crate) fn decimal(value: f64) -> Quantity {
+    Quantity::Decimal((value * 1000.0).round() / 1000.0)
+}
+
+impl Quantity {
+    /// Quantity multiplied by `factor`.
+    ///
+    /// Integers stay integers and fractions stay fractions when the result
+    /// allows it, otherwise the result is a decimal. Text and empty quantities
+    /// are unchanged.
+    pub fn scaled(&self, factor: f64) -> Quantity {
+        match self {
+            Quantity::Integer(i) => {
+                let value = *i as f64 * factor;
+                whole(value).map_or_else(|| decimal(value), Quantity::Integer)
+            }
+            Quantity::Fraction(n, d) => {
+                let value = *n as f64 / *d as f64 * factor;
+                // look for a denominator close to the original one, that fits
+                (1..=MAX_DENOMINATOR_FACTOR)
+                    .map_while(|m| d.checked_mul(m))
+                    .find_map(|d| whole(value * d as f64).map(|n| (n, d)))
+                    .map_or_else(|| decimal(value), |(n, d)| Quantity::ratio(n, d))
+            }
+            Quantity::Decimal(d) => decimal(d * factor),
+            Quantity::Empty | Quantity::Text(_) => self.clone(),
+        }
+    }
+}
+
+impl Recipe {
+    /// Multiply the quantity of every ingredient by `factor`, except the fixed
+    /// ones. Timers and cookware are left alone.
+    pub fn scale(&mut self, factor: f64) {
+        for step in self.steps.iter_mut() {
+            for part in step.parts.iter_mut() {
+                if let Part::Ingredient(ingredient) = part {
+                    if !ingredient.fixed {
+                        ingredient.quantity = ingredient.quantity.scaled(factor);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Scale the recipe from its `servings` metadata to `servings`, and update
+    /// the metadata, keeping its type.
+    ///
+    /// The metadata is read with [`KnownMetadata::servings`]; the quantities of
+    /// a range like `4-6` are for its lower bound, and those of options like
+    /// `2|4` for the first one. The scaled recipe has exactly `servings`.
+    pub fn scale_to_servings(&mut self, servings: u32) -> Result<(), ScaleError> {
+        let base = KnownMetadata::new(&self.metadata)
+            .servings()
+            .map_err(ScaleError::InvalidServings)?
+            .ok_or(ScaleError::MissingServings)?
+            .base();
+        self.scale(f64::from(servings) / f64::from(base));
+        let key = SERVINGS_KEYS
+            .iter()
+            .find(|&&key| self.metadata.contains_key(key));
+        if let Some(value) = key.and_then(|&key| self.metadata.get_mut(key)) {
+            *value = match value {
+                MetadataValue::Number(_) => MetadataValue::Number(f64::from(servings)),
+                _ => servings.to_string().into(),
+            };
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use indexmap::IndexMap;
+    use pretty_assertions::assert_eq;
+
+    fn quantities(recipe: &Recipe) -> Vec<String> {
+        recipe
+            .steps
+            .iter()
+            .flat_map(|step| step.parts.iter())
+            .filter_map(|part| match part {
+                Part::Ingredient(i) => Some(i.quantity.to_string()),
+                Part::Cookware(c) => Some(c.quantity.to_string()),
+                Part::Timer(t) => Some(t.quantity.to_string()),
+                Part::Text(_) | Part::Note(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_scaled() {
+        assert_eq!(Quantity::Integer(3).scaled(2.0), Quantity::Integer(6));
+        assert_eq!(Quantity::Integer(3).scaled(0.5), Quantity::Decimal(1.5));
+        assert_eq!(
+            Quantity::Integer(u64::MAX).scaled(2.0),
+            Quantity::Decimal(u64::MAX as f64 * 2.0)
+        );
+        assert_eq!(
+            Quantity::Integer(u64::MAX).scaled(1.0),
+            Quantity::Decimal(u64::MAX as f64)
+        );
+        assert_eq!(
+            Quantity::Fraction(1, 2).scaled(1.5),
+            Quantity::Fraction(3, 4)
+        );
+        assert_eq!(
+            Quantity::Fraction(1, 3).scaled(2.0),
+            Quantity::Fraction(2, 3)
+        );
+        assert_eq!(Quantity::Fraction(1, 2).scaled(4.0), Quantity::Integer(2));
+        assert_eq!(
+            Quantity::Fraction(3, 4).scaled(2.0),
+            Quantity::Fraction(3, 2)
+        );
+        assert_eq!(
+            Quantity::Fraction(1, 2).scaled(0.3),
+            Quantity::Fraction(3, 20)
+        );
+        assert_eq!(
+            Quantity::Fraction(1, 3).scaled(0.01),
+            Quantity::Decimal(0.003)
+        );
+        let tiny = Quantity::Fraction(1, u64::MAX / 2);
+        assert_eq!(tiny.scaled(0.5), Quantity::Fraction(1, u64::MAX - 1));
+        assert_eq!(tiny.scaled(1.0 / 3.0), Quantity::Decimal(0.0));
+        assert_eq!(Quantity::Decimal(0.1).scaled(3.0), Quantity::Decimal(0.3));
+        assert_eq!(
+            Quantity::Text("a pinch".to_string()).scaled(2.0),
+            Quantity::Text("a pinch".to_string())
+        );
+        assert_eq!(Quantity::Empty.scaled(2.0), Quantity::Empty);
+    }
+
+    #[test]
+    fn test_scale_to_servings() {
+        let mut recipe = parse(String::from(
+            ">> servings: 4\n\
+            Mix @flour{500%g}, @milk{1/2%l}, @eggs{3} and @salt{=1%tsp} in #bowl{2} for ~{10%min}",
+        ))
+        .unwrap();
+        recipe.scale_to_servings(6).unwrap();
+        assert_eq!(
+            quantities(&recipe),
+            vec!["750", "3/4", "4.5", "1", "2", "10"]
+        );
+        assert_eq!(recipe.metadata["servings"], "6");
+    }
+
+    #[test]
+    fn test_servings() {
+        let scaled = |s: &str| {
+            let mut recipe = parse(format!("{s}\nAdd @flour{{100%g}}")).unwrap();
+            recipe
+                .scale_to_servings(8)
+                .map(|()| (quantities(&recipe)[0].clone(), recipe.metadata))
+        };
+        let metadata = |key: &str, value: &str| {
+            IndexMap::from([(key.to_string(), MetadataValue::from(value.to_string()))])
+        };
+        assert_eq!(
+            scaled(">> servings: 2|4"),
+            Ok(("400".to_string(), metadata("servings", "8")))
+        );
+        assert_eq!(
+            scaled(">> servings: 4-6"),
+            Ok(("200".to_string(), metadata("servings", "8")))
+        );
+        assert_eq!(
+            scaled(">> serves: 4"),
+            Ok(("200".to_string(), metadata("serves", "8")))
+        );
+        assert_eq!(scaled(""), Err(ScaleError::MissingServings));
+        let invalid = |value: &str| {
+            Err(ScaleError::InvalidServings(MetadataError {
+                key: "servings".to_string(),
+                value: value.to_string(),
+                expected: "a number of servings like `4`, `4-6` or `2|4`",
+            }))
+        };
+        assert_eq!(scaled(">> servings: a few"), invalid("a few"));
+        assert_eq!(scaled(">> servings: 0"), invalid("0"));
+        assert_eq!(scaled(">> servings: 1.5"), invalid("1.5"));
+    }
+}
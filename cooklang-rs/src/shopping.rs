@@ -0,0 +1,244 @@
+use crate::parser::{gcd, Ingredient, Part, Quantity, Recipe};
+use crate::scale::decimal;
+use crate::units::UnitsTable;
+
+/// An entry of a shopping list
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShoppingItem {
+    pub name: String,
+    /// sum of the numeric quantities, or a single text or empty quantity
+    pub quantity: Quantity,
+    pub units: String,
+}
+
+/// sum of two numeric quantities, exact for integers and fractions
+fn add(a: &Quantity, b: &Quantity) -> Quantity {
+    let ratio = |q: &Quantity| match q {
+        Quantity::Integer(i) => Some((*i, 1)),
+        Quantity::Fraction(n, d) => Some((*n, *d)),
+        _ => None,
+    };
+    let sum = || a.value().unwrap_or_default() + b.value().unwrap_or_default();
+    match (ratio(a), ratio(b)) {
+        (Some((n1, d1)), Some((n2, d2))) => {
+            // over the least common denominator, decimal when it overflows
+            let divisor = gcd(d1, d2).max(1);
+            let exact = (d1 / divisor).checked_mul(d2).and_then(|d| {
+                let n = n1
+                    .checked_mul(d2 / divisor)?
+                    .checked_add(n2.checked_mul(d1 / divisor)?)?;
+                Some(Quantity::ratio(n, d))
+            });
+            exact.unwrap_or_else(|| decimal(sum()))
+        }
+        _ => decimal(sum()),
+    }
+}
+
+/// Ingredients gathered from several recipes.
+///
+/// Ingredient notes are left out. The quantity of a reference like
+/// `@&flour{100%g}` is added to the entry of its definition. Numeric
+/// quantities of the same ingredient are summed when their units are the
+/// same or convertible, other quantities are kept as separate entries.
+#[derive(Debug, Default)]
+pub struct ShoppingList {
+    items: Vec<ShoppingItem>,
+    units: UnitsTable,
+}
+
+impl ShoppingList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shopping list converting units with `units` instead of the bundled table
+    pub fn with_units(units: UnitsTable) -> Self {
+        ShoppingList {
+            items: Vec::new(),
+            units,
+        }
+    }
+
+    /// Add the ingredients of `recipe`, except the hidden ones, which are
+    /// left out of the ingredients of the recipe too
+    pub fn add_recipe(&mut self, recipe: &Recipe) {
+        for step in &recipe.steps {
+            for part in &step.parts {
+                match part {
+                    Part::Ingredient(ingredient) if !ingredient.hidden => {
+                        self.add_ingredient(ingredient)
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Add an ingredient, hidden or not. A reference without a quantity adds
+    /// nothing, its definition is already counted.
+    pub fn add_ingredient(&mut self, ingredient: &Ingredient) {
+        if ingredient.reference && ingredient.quantity == Quantity::Empty {
+            return;
+        }
+        let same_name = |item: &&mut ShoppingItem| item.name == ingredient.name;
+        match &ingredient.quantity {
+            q if q.value().is_some() => {
+                let units = &self.units;
+                let existing = self
+                    .items
+                    .iter_mut()
+                    .filter(same_name)
+                    .filter(|item| item.quantity.value().is_some())
+                    .find_map(|item| {
+                        units
+                            .factor(&ingredient.units, &item.units)
+                            .map(|factor| (item, factor))
+                    });
+                match existing {
+                    Some((item, factor)) => {
+                        item.quantity = add(&item.quantity, &q.scaled(factor));
+                    }
+                    None => self.push(ingredient),
+                }
+            }
+            Quantity::Empty => {
+                let known =
+                    self.items.iter_mut().filter(same_name).any(|item| {
+                        item.quantity == Quantity::Empty && item.units == ingredient.units
+                    });
+                if !known {
+                    self.push(ingredient);
+                }
+            }
+            _ => self.push(ingredient),
+        }
+    }
+
+    fn push(&mut self, ingredient: &Ingredient) {
+        self.items.push(ShoppingItem {
+            name: ingredient.name.clone(),
+            quantity: ingredient.quantity.clone(),
+            units: ingredient.units.clone(),
+        });
+    }
+
+    /// Entries sorted by name then units, keeping the order of addition otherwise
+    pub fn items(&self) -> Vec<ShoppingItem> {
+        let mut items = self.items.clone();
+        items.sort_by(|a, b| (&a.name, &a.units).cmp(&(&b.name, &b.units)));
+        items
+    }
+}
+
+/// Shopping list of all the ingredients of `recipes`
+pub fn shopping_list<'a>(recipes: impl IntoIterator<Item = &'a Recipe>) -> Vec<ShoppingItem> {
+    let mut list = ShoppingList::new();
+    for recipe in recipes {
+        list.add_recipe(recipe);
+    }
+    list.items()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use pretty_assertions::assert_eq;
+
+    fn item(name: &str, quantity: Quantity, units: &str) -> ShoppingItem {
+        ShoppingItem {
+            name: name.to_string(),
+            quantity,
+            units: units.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_add() {
+        assert_eq!(
+            add(&Quantity::Fraction(1, 2), &Quantity::Fraction(1, 4)),
+            Quantity::Fraction(3, 4)
+        );
+        assert_eq!(
+            add(&Quantity::Integer(1), &Quantity::Fraction(1, 2)),
+            Quantity::Fraction(3, 2)
+        );
+        assert_eq!(
+            add(&Quantity::Fraction(1, 2), &Quantity::Fraction(1, 2)),
+            Quantity::Integer(1)
+        );
+        assert_eq!(
+            add(&Quantity::Decimal(0.1), &Quantity::Integer(2)),
+            Quantity::Decimal(2.1)
+        );
+        let small = Quantity::Fraction(1, 1 << 40);
+        assert_eq!(add(&small, &small), Quantity::Fraction(1, 1 << 39));
+        assert_eq!(
+            add(
+                &Quantity::Fraction(1, 100_000_000_000),
+                &Quantity::Fraction(1, 100_000_000_001)
+            ),
+            Quantity::Decimal(0.0)
+        );
+        assert_eq!(
+            add(
+                &Quantity::Fraction(200_000_000_000, 300_000_000_001),
+                &Quantity::Fraction(1, 100_000_000_000)
+            ),
+            Quantity::Decimal(0.667)
+        );
+    }
+
+    #[test]
+    fn test_shopping_list() {
+        let pancakes = parse(String::from(
+            "Mix @flour{250%g}, @milk{1/2%l}, @eggs{2}(beaten) and @salt",
+        ))
+        .unwrap();
+        let bread = parse(String::from(
+            "Knead @flour{1%kilo}, @&flour{100%g} with @water{300%ml}, @salt{a pinch} and @eggs{1%tbsp}.\n\
+            Brush with @milk{1/4%l}, @-water{50%ml} and add @salt and @&eggs",
+        ))
+        .unwrap();
+        assert_eq!(
+            shopping_list([&pancakes, &bread]),
+            vec![
+                item("eggs", Quantity::Integer(2), ""),
+                item("eggs", Quantity::Integer(1), "tbsp"),
+                item("flour", Quantity::Integer(1350), "g"),
+                item("milk", Quantity::Fraction(3, 4), "l"),
+                item("salt", Quantity::Empty, ""),
+                item("salt", Quantity::Text("a pinch".to_string()), ""),
+                item("water", Quantity::Integer(300), "ml"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hidden_and_references() {
+        let recipe = parse(String::from(
+            "Boil @water{1%l} with @-salt{1%tsp}, then add @&water{250%ml} and @&water",
+        ))
+        .unwrap();
+        assert_eq!(
+            shopping_list([&recipe]),
+            vec![item("water", Quantity::Decimal(1.25), "l")]
+        );
+        let mut list = ShoppingList::new();
+        for ingredient in recipe.ingredients() {
+            list.add_ingredient(ingredient);
+        }
+        let Part::Ingredient(salt) = &recipe.steps[0].parts[3] else {
+            panic!("no hidden salt");
+        };
+        list.add_ingredient(salt);
+        assert_eq!(
+            list.items(),
+            vec![
+                item("salt", Quantity::Integer(1), "tsp"),
+                item("water", Quantity::Integer(1), "l"),
+            ]
+        );
+    }
+}
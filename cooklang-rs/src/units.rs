@@ -0,0 +1,431 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
+
+use crate::parser::{Part, Quantity, Recipe};
+
+/// Table of the units known by default, see its comments for the format
+const BUNDLED: &str = include_str!("units.toml");
+
+/// converted quantities are rounded to this many significant digits
+const SIGNIFICANT_DIGITS: i32 = 3;
+
+/// Error returned by the units table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnitsError {
+    /// the TOML table is not valid
+    Table(String),
+    /// the units are not in the table
+    UnknownUnit(String),
+    /// the units do not measure the same physical quantity
+    Incompatible(String, String),
+}
+
+impl fmt::Display for UnitsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnitsError::Table(e) => write!(f, "invalid units table: {e}"),
+            UnitsError::UnknownUnit(u) => write!(f, "unknown unit `{u}`"),
+            UnitsError::Incompatible(from, to) => {
+                write!(f, "can not convert `{from}` to `{to}`")
+            }
+        }
+    }
+}
+
+impl std::error::Error for UnitsError {}
+
+/// What a unit measures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhysicalQuantity {
+    Mass,
+    Volume,
+    Length,
+    Temperature,
+    Time,
+}
+
+/// System of measurement a recipe can be converted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum System {
+    Metric,
+    Imperial,
+}
+
+/// A unit of the table
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unit {
+    pub name: String,
+    /// short name used when a quantity is converted to this unit
+    pub symbol: String,
+    /// other names of the unit, like plurals
+    pub aliases: Vec<String>,
+    pub quantity: PhysicalQuantity,
+    /// units without a system, like time, are never converted by `convert_recipe`
+    pub system: Option<System>,
+    /// size of the unit in the base unit of its quantity
+    pub ratio: f64,
+    /// added to a value before multiplying it by the ratio
+    pub difference: f64,
+    /// the unit can be picked when converting to its system
+    pub preferred: bool,
+}
+
+impl Unit {
+    /// read a `[[units]]` entry of a table
+    fn from_toml(entry: &toml::Value) -> Result<Unit, String> {
+        let entry = entry.as_table().ok_or("not a table")?;
+        let string = |key: &str| {
+            entry
+                .get(key)
+                .map(|v| v.as_str().ok_or(format!("`{key}` is not a string")))
+                .transpose()
+        };
+        let required = |key: &str| string(key)?.ok_or(format!("missing `{key}`"));
+        let number = |key: &str| match entry.get(key) {
+            None => Ok(None),
+            Some(toml::Value::Integer(i)) => Ok(Some(*i as f64)),
+            Some(toml::Value::Float(f)) => Ok(Some(*f)),
+            Some(_) => Err(format!("`{key}` is not a number")),
+        };
+        let name = required("name")?.to_string();
+        let symbol = required("symbol")?.to_string();
+        let aliases = match entry.get("aliases") {
+            None => Vec::new(),
+            Some(aliases) => aliases
+                .as_array()
+                .and_then(|a| a.iter().map(|a| a.as_str().map(String::from)).collect())
+                .ok_or("`aliases` is not a list of strings")?,
+        };
+        let quantity = match required("quantity")? {
+            "mass" => PhysicalQuantity::Mass,
+            "volume" => PhysicalQuantity::Volume,
+            "length" => PhysicalQuantity::Length,
+            "temperature" => PhysicalQuantity::Temperature,
+            "time" => PhysicalQuantity::Time,
+            q => return Err(format!("unknown quantity `{q}`")),
+        };
+        let system = match string("system")? {
+            None => None,
+            Some("metric") => Some(System::Metric),
+            Some("imperial") => Some(System::Imperial),
+            Some(s) => return Err(format!("unknown system `{s}`")),
+        };
+        let preferred = entry
+            .get("preferred")
+            .map(|v| v.as_bool().ok_or("`preferred` is not a boolean"))
+            .transpose()?;
+        Ok(Unit {
+            name,
+            symbol,
+            aliases,
+            quantity,
+            system,
+            ratio: number("ratio")?.ok_or("missing `ratio`")?,
+            difference: number("difference")?.unwrap_or_default(),
+            preferred: preferred.unwrap_or_default(),
+        })
+    }
+
+    fn base_value(&self, value: f64) -> f64 {
+        (value + self.difference) * self.ratio
+    }
+
+    fn unit_value(&self, value: f64) -> f64 {
+        value / self.ratio - self.difference
+    }
+
+    /// names the unit can be written with
+    fn names(&self) -> impl Iterator<Item = &String> {
+        [&self.name, &self.symbol].into_iter().chain(&self.aliases)
+    }
+}
+
+/// `value` rounded to `SIGNIFICANT_DIGITS`, as an integer when it is whole
+fn rounded(value: f64) -> Quantity {
+    if value == 0.0 {
+        return Quantity::Integer(0);
+    }
+    let magnitude = value.abs().log10().floor() as i32;
+    let scale = 10f64.powi(SIGNIFICANT_DIGITS - 1 - magnitude);
+    let value = (value * scale).round() / scale;
+    if value.fract() == 0.0 && value >= 0.0 {
+        Quantity::Integer(value as u64)
+    } else {
+        Quantity::Decimal(value)
+    }
+}
+
+/// Units recognized by name, symbol or alias.
+///
+/// Names are matched exactly first, then ignoring case, so `c` is a cup and
+/// `C` degrees Celsius. `UnitsTable::default()` is the bundled table.
+#[derive(Debug, Clone)]
+pub struct UnitsTable {
+    units: Vec<Unit>,
+    exact: HashMap<String, usize>,
+    lowercase: HashMap<String, usize>,
+}
+
+impl Default for UnitsTable {
+    /// a copy of the bundled table, which is read once
+    fn default() -> Self {
+        Self::bundled().clone()
+    }
+}
+
+impl UnitsTable {
+    /// The bundled table, read once
+    pub fn bundled() -> &'static UnitsTable {
+        static BUNDLED_TABLE: OnceLock<UnitsTable> = OnceLock::new();
+        BUNDLED_TABLE
+            .get_or_init(|| Self::from_toml(BUNDLED).expect("the bundled units table is valid"))
+    }
+
+    /// Table with only the units of `toml`
+    pub fn from_toml(toml: &str) -> Result<Self, UnitsError> {
+        let mut table = UnitsTable {
+            units: Vec::new(),
+            exact: HashMap::new(),
+            lowercase: HashMap::new(),
+        };
+        table.extend_from_toml(toml)?;
+        Ok(table)
+    }
+
+    /// Add the units of a user supplied `toml` table, in the bundled format.
+    /// Names already known are taken over by the new units.
+    pub fn extend_from_toml(&mut self, toml: &str) -> Result<(), UnitsError> {
+        let file: toml::Table = toml
+            .parse()
+            .map_err(|e: toml::de::Error| UnitsError::Table(e.to_string()))?;
+        let entries = match file.get("units") {
+            None => return Ok(()),
+            Some(units) => units
+                .as_array()
+                .ok_or_else(|| UnitsError::Table("`units` is not a list".to_string()))?,
+        };
+        // read every unit before adding any
+        let units = entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                Unit::from_toml(entry)
+                    .map_err(|e| UnitsError::Table(format!("unit {}: {e}", i + 1)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for unit in units {
+            self.add(unit);
+        }
+        Ok(())
+    }
+
+    pub fn add(&mut self, unit: Unit) {
+        let index = self.units.len();
+        for name in unit.names() {
+            self.exact.insert(name.clone(), index);
+            self.lowercase.insert(name.to_lowercase(), index);
+        }
+        self.units.push(unit);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Unit> {
+        let name = name.trim();
+        self.exact
+            .get(name)
+            .or_else(|| self.lowercase.get(&name.to_lowercase()))
+            .map(|&i| &self.units[i])
+    }
+
+    fn get_or_err(&self, name: &str) -> Result<&Unit, UnitsError> {
+        self.get(name)
+            .ok_or_else(|| UnitsError::UnknownUnit(name.to_string()))
+    }
+
+    /// Convert `value` from `from` units to `to` units
+    pub fn convert(&self, value: f64, from: &str, to: &str) -> Result<f64, UnitsError> {
+        let (from_unit, to_unit) = (self.get_or_err(from)?, self.get_or_err(to)?);
+        if from_unit.quantity != to_unit.quantity {
+            return Err(UnitsError::Incompatible(from.to_string(), to.to_string()));
+        }
+        Ok(to_unit.unit_value(from_unit.base_value(value)))
+    }
+
+    /// Factor to multiply a quantity in `from` units by to get `to` units.
+    /// Units with a difference, like degrees Fahrenheit, have none.
+    pub fn factor(&self, from: &str, to: &str) -> Option<f64> {
+        if from == to {
+            return Some(1.0);
+        }
+        let (from, to) = (self.get(from)?, self.get(to)?);
+        (from.quantity == to.quantity && from.difference == 0.0 && to.difference == 0.0)
+            .then(|| from.ratio / to.ratio)
+    }
+
+    /// `quantity` of `units` in the preferred units of `system`, or `None`
+    /// when there is nothing to convert
+    pub fn convert_quantity(
+        &self,
+        quantity: &Quantity,
+        units: &str,
+        system: System,
+    ) -> Option<(Quantity, String)> {
+        let unit = self.get(units)?;
+        if unit.system.is_none_or(|s| s == system) {
+            return None;
+        }
+        let base = unit.base_value(quantity.value()?);
+        let mut candidates: Vec<&Unit> = self
+            .units
+            .iter()
+            .filter(|u| u.preferred && u.quantity == unit.quantity && u.system == Some(system))
+            .collect();
+        candidates.sort_by(|a, b| a.ratio.total_cmp(&b.ratio));
+        // the largest unit that keeps the value above one
+        let target = candidates
+            .iter()
+            .rev()
+            .find(|u| u.unit_value(base) >= 1.0)
+            .or_else(|| candidates.first())?;
+        Some((rounded(target.unit_value(base)), target.symbol.clone()))
+    }
+
+    /// Convert the ingredients and timers of `recipe` to `system`. Text
+    /// quantities and unknown units are left alone.
+    pub fn convert_recipe(&self, recipe: &mut Recipe, system: System) {
+        for part in recipe.steps.iter_mut().flat_map(|s| s.parts.iter_mut()) {
+            let (quantity, units) = match part {
+                Part::Ingredient(i) => (&mut i.quantity, &mut i.units),
+                Part::Timer(t) => (&mut t.quantity, &mut t.units),
+                Part::Cookware(_) | Part::Text(_) | Part::Note(_) => continue,
+            };
+            if let Some((q, u)) = self.convert_quantity(quantity, units, system) {
+                *quantity = q;
+                *units = u;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use pretty_assertions::assert_eq;
+
+    fn ingredients(recipe: &Recipe) -> Vec<String> {
+        recipe
+            .ingredients()
+            .iter()
+            .map(|i| format!("{} {}", i.quantity, i.units))
+            .collect()
+    }
+
+    #[test]
+    fn test_get() {
+        let table = UnitsTable::default();
+        let name = |units: &str| table.get(units).map(|u| u.name.as_str());
+        assert_eq!(name("g"), Some("gram"));
+        assert_eq!(name("Grams"), Some("gram"));
+        assert_eq!(name(" kg "), Some("kilogram"));
+        assert_eq!(name("c"), Some("cup"));
+        assert_eq!(name("C"), Some("celsius"));
+        assert_eq!(name("fl oz"), Some("fluid ounce"));
+        assert_eq!(name("pinch"), None);
+    }
+
+    #[test]
+    fn test_convert() {
+        let table = UnitsTable::default();
+        assert_eq!(table.convert(1.5, "kg", "g"), Ok(1500.0));
+        assert_eq!(table.convert(2.0, "hours", "min"), Ok(120.0));
+        assert!((table.convert(180.0, "°C", "°F").unwrap() - 356.0).abs() < 1e-9);
+        assert!((table.convert(0.0, "C", "K").unwrap() - 273.15).abs() < 1e-9);
+        assert_eq!(
+            table.convert(1.0, "g", "ml"),
+            Err(UnitsError::Incompatible("g".to_string(), "ml".to_string()))
+        );
+        assert_eq!(
+            table.convert(1.0, "g", "pinch"),
+            Err(UnitsError::UnknownUnit("pinch".to_string()))
+        );
+        assert_eq!(table.factor("l", "ml"), Some(1000.0));
+        assert_eq!(table.factor("°C", "°F"), None);
+    }
+
+    #[test]
+    fn test_convert_recipe() {
+        let table = UnitsTable::default();
+        let mut recipe = parse(String::from(
+            "Mix @flour{500%g}, @butter{1.5%lb}, @milk{1/2%cup}, @sugar{2%tbsp}, \
+            @water{2%l} and @salt{a pinch%g}. Bake at @oven{180%°C} for ~{1%hour}",
+        ))
+        .unwrap();
+
+        table.convert_recipe(&mut recipe, System::Imperial);
+        assert_eq!(
+            ingredients(&recipe),
+            vec![
+                "1.1 lb",
+                "1.5 lb",
+                "1/2 cup",
+                "2 tbsp",
+                "8.45 cup",
+                "a pinch g",
+                "356 °F"
+            ]
+        );
+        assert_eq!(recipe.timers()[0].units, "hour");
+
+        table.convert_recipe(&mut recipe, System::Metric);
+        assert_eq!(
+            ingredients(&recipe),
+            vec![
+                "499 g",
+                "680 g",
+                "118 ml",
+                "29.6 ml",
+                "2 l",
+                "a pinch g",
+                "180 °C"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_user_table() {
+        let mut table = UnitsTable::default();
+        table
+            .extend_from_toml(
+                r#"
+                [[units]]
+                name = "cup (UK)"
+                symbol = "cup (UK)"
+                aliases = ["UK cup"]
+                quantity = "volume"
+                system = "imperial"
+                ratio = 284.130625
+                "#,
+            )
+            .unwrap();
+        assert!((table.convert(1.0, "uk cup", "ml").unwrap() - 284.130625).abs() < 1e-9);
+        assert_eq!(table.get("cup").unwrap().name, "cup");
+
+        assert_eq!(
+            table.extend_from_toml("[[units]]\nname = \"bad\""),
+            Err(UnitsError::Table("unit 1: missing `symbol`".to_string()))
+        );
+        assert_eq!(
+            table.extend_from_toml(
+                "[[units]]\nname = \"bad\"\nsymbol = \"b\"\nquantity = \"mood\"\nratio = 1"
+            ),
+            Err(UnitsError::Table(
+                "unit 1: unknown quantity `mood`".to_string()
+            ))
+        );
+        assert!(matches!(
+            table.extend_from_toml("units = 1"),
+            Err(UnitsError::Table(_))
+        ));
+    }
+}
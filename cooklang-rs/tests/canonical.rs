@@ -1,4 +1,5 @@
-use cooklang_rs::parser::{parse, Part};
+use cooklang_rs::format::format;
+use cooklang_rs::parser::{self, parse, parse_borrowed, to_cooklang, Part, Recipe, Span};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fmt;
@@ -25,6 +26,32 @@ impl fmt::Display for Quantity {
     }
 }
 
+impl Quantity {
+    /// check that the parsed quantity matches, `default` being the canonical value of an empty quantity
+    fn assert_matches(&self, parsed: &parser::Quantity, default: &str) {
+        if parsed == &parser::Quantity::Empty {
+            assert_eq!(self.to_string(), default);
+            return;
+        }
+        match (self, parsed.value()) {
+            (Quantity::Int(a), Some(b)) => assert_eq!(*a as f64, b),
+            (Quantity::Float(a), Some(b)) => assert_eq!(*a as f64, b as f32 as f64),
+            (Quantity::Str(a), None) => assert_eq!(a, &parsed.to_string()),
+            _ => panic!("{self:?} != {parsed:?}"),
+        }
+    }
+
+    /// check that the serialized quantity is the canonical one
+    #[cfg(feature = "serde")]
+    fn assert_json(&self, json: &serde_json::Value) {
+        match self {
+            Quantity::Int(a) => assert_eq!(json.as_f64(), Some(*a as f64)),
+            Quantity::Float(a) => assert_eq!(json.as_f64().map(|b| b as f32), Some(*a)),
+            Quantity::Str(a) => assert_eq!(json.as_str(), Some(a.as_str())),
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 struct StepTNQU {
     t: String,
@@ -76,75 +103,181 @@ struct CanonicalTests {
 fn test_canonical() {
     let tests: CanonicalTests = serde_yaml::from_str(include_str!("canonical.yaml")).unwrap();
     println!("canonical tests version {}", tests.version);
-    for (name, mut test) in tests.tests {
+    for (name, test) in tests.tests {
         println!("test {name}");
-        if name == "testTimerFractional"
-            || name == "testFractionsWithSpaces"
-            || name == "testFractions"
-        {
-            // skip
-            continue;
+        let out = parse(test.source).unwrap();
+
+        println!("metadata {:?}", out.metadata);
+        assert_eq!(out.metadata.len(), test.result.metadata.len());
+        for (key, value) in out.metadata {
+            assert_eq!(
+                value.as_str(),
+                test.result.metadata.get(&key).map(String::as_str)
+            );
         }
-        let out = parse(test.source);
-
-        let mut step_indice = 0;
-        for out_step in out.into_iter() {
-            // 2 cases :
-            // - a vect with a single metadata
-            // - a vect with a multiple other steps
-            if out_step.len() == 1 {
-                if let Part::Metadata(metadata) = out_step.get(0).unwrap() {
-                    println!("metadata key {}", metadata.key);
-                    println!("metadata possible keys {:?}", test.result.metadata.keys());
-                    assert!(test.result.metadata.contains_key(&metadata.key));
-                    let v = test.result.metadata.remove(&metadata.key).unwrap();
-                    assert_eq!(metadata.value, v);
-                    continue;
-                }
-            }
-            // compare with test.result.steps.get(step_indice)
-            let steps = test.result.steps.get(step_indice).unwrap();
-            step_indice += 1;
+
+        assert_eq!(out.steps.len(), test.result.steps.len());
+        for (steps, out_step) in test.result.steps.iter().zip(out.steps) {
             println!("{:?}, \n{:?}", steps, out_step);
-            println!("{:?}, {:?}", steps.len(), out_step.len());
-            assert_eq!(steps.len(), out_step.len());
-            for (a, b) in steps.iter().zip(out_step.into_iter()) {
+            println!("{:?}, {:?}", steps.len(), out_step.parts.len());
+            assert_eq!(steps.len(), out_step.parts.len());
+            for (a, b) in steps.iter().zip(out_step.parts) {
                 match (a, b) {
-                    (Step::Tv(t), Part::Text(string)) => {
+                    (Step::Tv(t), Part::Text(text)) => {
                         assert_eq!(t.t, "text");
-                        assert_eq!(t.value.trim(), string);
+                        assert_eq!(t.value.trim(), text.value);
                     }
                     (Step::Tnqu(t), Part::Timer(t2)) => {
                         assert_eq!(t2.name, t.name);
                         assert_eq!(t2.units, t.units);
                         assert_eq!(t.t, "timer");
-                        assert_eq!(t2.quantity, t.quantity.to_string());
+                        t.quantity.assert_matches(&t2.quantity, "");
                     }
                     (Step::Tnq(t), Part::Cookware(cookware)) => {
                         assert_eq!(t.t, "cookware");
                         assert_eq!(cookware.name, t.name);
-                        if !cookware.quantity.is_empty() {
-                            assert_eq!(cookware.quantity, t.quantity.to_string());
-                        } else {
-                            assert_eq!(t.quantity.to_string(), "1");
-                        }
+                        t.quantity.assert_matches(&cookware.quantity, "1");
                     }
                     (Step::Tnqu(t), Part::Ingredient(ingredient)) => {
                         assert_eq!(t.t, "ingredient");
                         assert_eq!(ingredient.name, t.name);
                         assert_eq!(ingredient.units, t.units);
-                        if !ingredient.quantity.is_empty() {
-                            assert_eq!(ingredient.quantity, t.quantity.to_string());
-                        } else {
-                            assert_eq!(t.quantity.to_string(), "some");
-                        }
+                        t.quantity.assert_matches(&ingredient.quantity, "some");
                     }
                     _ => panic!(),
                 }
             }
         }
+    }
+}
+
+/// reset the spans, which change when a recipe is written back
+fn without_spans(mut recipe: Recipe) -> Recipe {
+    for step in recipe.steps.iter_mut() {
+        step.span = Span::default();
+        for part in step.parts.iter_mut() {
+            match part {
+                Part::Cookware(c) => c.span = Span::default(),
+                Part::Timer(t) => t.span = Span::default(),
+                Part::Ingredient(i) => i.span = Span::default(),
+                Part::Text(t) => t.span = Span::default(),
+                Part::Note(n) => n.span = Span::default(),
+            }
+        }
+    }
+    for section in recipe.sections.iter_mut() {
+        section.span = Span::default();
+    }
+    recipe
+}
+
+#[test]
+fn test_canonical_write_round_trip() {
+    let tests: CanonicalTests = serde_yaml::from_str(include_str!("canonical.yaml")).unwrap();
+    for (name, test) in tests.tests {
+        println!("test {name}");
+        let recipe = parse(test.source).unwrap();
+        let written = to_cooklang(&recipe);
+        println!("{written}");
+        assert_eq!(
+            without_spans(parse(written).unwrap()),
+            without_spans(recipe)
+        );
+    }
+    let sources = [
+        "  >> not: metadata",
+        "Mix\n  >> a: b\n\n\t>>",
+        ">> a: b\n >> c: d",
+        "[- tip -] = 2 cups of water",
+        "[- tip -] > not a note\n\u{a0}=\u{a0}> b",
+        "==",
+        "==\n== B ==\nMix\n==\nStir",
+        "Stir -[- x -]- and [[- y -]- then\n> a -[--]- b\n>> k: a-[--]-b\n== a -[--]- ==",
+    ];
+    for source in sources {
+        let recipe = parse(source.to_string()).unwrap();
+        let written = to_cooklang(&recipe);
+        assert_eq!(
+            without_spans(parse(written).unwrap()),
+            without_spans(recipe)
+        );
+    }
+}
+
+#[test]
+fn test_canonical_format() {
+    let tests: CanonicalTests = serde_yaml::from_str(include_str!("canonical.yaml")).unwrap();
+    for (name, test) in tests.tests {
+        println!("test {name}");
+        let formatted = format(&test.source).unwrap();
+        println!("{formatted}");
+        assert_eq!(format(&formatted).unwrap(), formatted);
+        assert_eq!(
+            without_spans(parse(formatted).unwrap()),
+            without_spans(parse(test.source).unwrap())
+        );
+    }
+}
+
+#[test]
+fn test_canonical_borrowed() {
+    let tests: CanonicalTests = serde_yaml::from_str(include_str!("canonical.yaml")).unwrap();
+    for (name, test) in tests.tests {
+        println!("test {name}");
+        let borrowed = parse_borrowed(&test.source).unwrap().into_owned();
+        assert_eq!(borrowed, parse(test.source).unwrap());
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_canonical_json_round_trip() {
+    let tests: CanonicalTests = serde_yaml::from_str(include_str!("canonical.yaml")).unwrap();
+    for (name, test) in tests.tests {
+        println!("test {name}");
+        let recipe = parse(test.source).unwrap();
+        let json = serde_json::to_value(&recipe).unwrap();
+
+        // same values as the canonical result
+        for (key, value) in &test.result.metadata {
+            assert_eq!(json["metadata"][key], value.as_str());
+        }
+        let steps = json["steps"].as_array().unwrap();
+        assert_eq!(steps.len(), test.result.steps.len());
+        for (step, expected) in steps.iter().zip(test.result.steps.iter()) {
+            let parts = step.as_array().unwrap();
+            assert_eq!(parts.len(), expected.len());
+            for (part, expected) in parts.iter().zip(expected) {
+                let (t, keys) = match expected {
+                    Step::Tv(t) => {
+                        assert_eq!(part["value"], t.value.trim());
+                        (&t.t, vec!["type", "value"])
+                    }
+                    Step::Tnqu(t) => {
+                        assert_eq!(part["name"], t.name.as_str());
+                        assert_eq!(part["units"], t.units.as_str());
+                        t.quantity.assert_json(&part["quantity"]);
+                        (&t.t, vec!["name", "quantity", "type", "units"])
+                    }
+                    Step::Tnq(t) => {
+                        assert_eq!(part["name"], t.name.as_str());
+                        t.quantity.assert_json(&part["quantity"]);
+                        (&t.t, vec!["name", "quantity", "type"])
+                    }
+                };
+                assert_eq!(&part["type"], t.as_str());
+                let mut part_keys: Vec<&str> = part
+                    .as_object()
+                    .unwrap()
+                    .keys()
+                    .map(String::as_str)
+                    .collect();
+                part_keys.sort();
+                assert_eq!(part_keys, keys);
+            }
+        }
 
-        // check that all metadata has been processed
-        assert_eq!(test.result.metadata.len(), 0)
+        let back: parser::Recipe = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&back).unwrap(), json);
     }
 }